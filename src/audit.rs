@@ -0,0 +1,243 @@
+use humantime::{format_rfc3339_seconds, parse_rfc3339};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{env, fmt, mem};
+
+/// Once it grows beyond this many bytes, the log is moved aside on unlock. Only the failures since
+/// the last unlock are summarized, so nothing before it is needed anymore.
+const MAX_LOG_SIZE: u64 = 64 * 1024;
+
+/// Failed attempts since the previous unlock.
+pub struct Summary {
+    pub failures: usize,
+    pub since: SystemTime,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = if self.failures == 1 { "" } else { "s" };
+        write!(f, "{} failed attempt{} since {}", self.failures, plural, local_time(self.since))
+    }
+}
+
+/// Records failed unlock attempts as JSON lines. Only the time, the number of the attempt and the
+/// length of the password are written, never the password itself. Rejected fingerprints are
+/// recorded as failures without a length.
+pub struct AuditLog {
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// Use $XDG_STATE_HOME/waylock/attempts.jsonl, falling back to $HOME/.local/state.
+    pub fn new() -> Self {
+        let path = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+            .map(|state_home| state_home.join("waylock/attempts.jsonl"));
+        if path.is_none() {
+            log::warn!("Neither XDG_STATE_HOME nor HOME are set, failed attempts won't be logged");
+        }
+        Self { path }
+    }
+
+    /// The directory the log is written to, if any.
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+
+    pub fn record_failure(&self, now: SystemTime, attempt: u32, length: usize) {
+        self.append(&format!(
+            "{{\"time\":\"{}\",\"event\":\"failure\",\"attempt\":{},\"length\":{}}}\n",
+            format_rfc3339_seconds(now),
+            attempt,
+            length
+        ));
+    }
+
+    pub fn record_fingerprint_failure(&self, now: SystemTime, attempt: u32) {
+        self.append(&format!(
+            "{{\"time\":\"{}\",\"event\":\"failure\",\"method\":\"fingerprint\",\"attempt\":{}}}\n",
+            format_rfc3339_seconds(now),
+            attempt
+        ));
+    }
+
+    /// Mark a successful unlock by `user`, returning a summary of the failed attempts since the
+    /// previous one if there were any. A log grown too large is moved aside to attempts.jsonl.old,
+    /// replacing the previous one.
+    pub fn record_unlock(&self, now: SystemTime, user: &str) -> Option<Summary> {
+        let summary = self.path.as_ref().and_then(|path| match fs::read_to_string(path) {
+            Ok(contents) => summarize(&contents),
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    log::error!("Failed to read {}: {}", path.display(), err);
+                }
+                None
+            }
+        });
+        self.append(&format!(
+            "{{\"time\":\"{}\",\"event\":\"unlock\",\"user\":\"{}\"}}\n",
+            format_rfc3339_seconds(now),
+            escape(user)
+        ));
+        self.rotate();
+        summary
+    }
+
+    fn rotate(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.len() > MAX_LOG_SIZE => {
+                if let Err(err) = fs::rename(path, path.with_extension("jsonl.old")) {
+                    log::error!("Failed to rotate {}: {}", path.display(), err);
+                }
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Failed to read {}: {}", path.display(), err),
+        }
+    }
+
+    fn append(&self, line: &str) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), |dir| fs::DirBuilder::new().recursive(true).mode(0o700).create(dir))
+            .and_then(|()| OpenOptions::new().create(true).append(true).mode(0o600).open(path))
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(err) = result {
+            log::error!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Count the failures after the last unlock in the contents of the log.
+fn summarize(contents: &str) -> Option<Summary> {
+    let mut summary: Option<Summary> = None;
+    for line in contents.lines() {
+        match field(line, "event") {
+            Some("failure") => match field(line, "time").map(parse_rfc3339) {
+                Some(Ok(time)) => match &mut summary {
+                    Some(summary) => summary.failures += 1,
+                    None => summary = Some(Summary { failures: 1, since: time }),
+                },
+                _ => log::warn!("Ignoring malformed audit log entry {:?}", line),
+            },
+            Some("unlock") => summary = None,
+            _ => log::warn!("Ignoring malformed audit log entry {:?}", line),
+        }
+    }
+    summary
+}
+
+/// Returns the value of a string field of a log entry. Only handles the entries written above,
+/// none of which contain escaped quotes.
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\":\"", name);
+    let start = line.find(&key)? + key.len();
+    let len = line[start..].find('"')?;
+    Some(&line[start..start + len])
+}
+
+/// Escape `s` for use in a JSON string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Format the time of day in the local timezone, e.g. 14:02.
+fn local_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as libc::time_t,
+        Err(_) => return format_rfc3339_seconds(time).to_string(),
+    };
+
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return format_rfc3339_seconds(time).to_string();
+    }
+    format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditLog;
+    use humantime::parse_rfc3339;
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::{env, fs, process};
+
+    #[test]
+    fn summarize_since_last_unlock() {
+        let contents = concat!(
+            "{\"time\":\"2020-01-01T09:00:00Z\",\"event\":\"failure\",\"attempt\":1,\"length\":4}\n",
+            "{\"time\":\"2020-01-01T09:01:00Z\",\"event\":\"unlock\",\"user\":\"alice\"}\n",
+            "{\"time\":\"2020-01-01T14:02:00Z\",\"event\":\"failure\",\"attempt\":1,\"length\":8}\n",
+            "{\"time\":\"2020-01-01T14:03:00Z\",\"event\":\"failure\",\"attempt\":2,\"length\":9}\n",
+            "{\"time\":\"2020-01-01T14:04:00Z\",\"event\":\"failure\",\"method\":\"fingerprint\",\"attempt\":3}\n",
+        );
+        let summary = super::summarize(contents).unwrap();
+        assert_eq!(summary.failures, 3);
+        assert_eq!(summary.since, parse_rfc3339("2020-01-01T14:02:00Z").unwrap());
+    }
+
+    #[test]
+    fn summarize_nothing_after_unlock() {
+        let contents = concat!(
+            "{\"time\":\"2020-01-01T09:00:00Z\",\"event\":\"failure\",\"attempt\":1,\"length\":4}\n",
+            "{\"time\":\"2020-01-01T09:01:00Z\",\"event\":\"unlock\",\"user\":\"alice\"}\n",
+        );
+        assert!(super::summarize(contents).is_none());
+    }
+
+    #[test]
+    fn rotate_large_log() {
+        let dir = env::temp_dir().join(format!("waylock-audit-{}", process::id()));
+        let path = dir.join("attempts.jsonl");
+        let log = AuditLog { path: Some(path.clone()) };
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        // Small logs are kept as they are
+        log.record_failure(now, 1, 8);
+        assert_eq!(log.record_unlock(now, "alice").map(|summary| summary.failures), Some(1));
+        assert!(path.exists());
+
+        while fs::metadata(&path).unwrap().len() <= super::MAX_LOG_SIZE {
+            log.record_failure(now, 1, 8);
+        }
+        assert!(log.record_unlock(now, "alice").is_some());
+        assert!(!path.exists());
+        assert!(dir.join("attempts.jsonl.old").exists());
+
+        // The next unlock starts a fresh log
+        log.record_failure(now, 1, 8);
+        assert_eq!(log.record_unlock(now, "alice").map(|summary| summary.failures), Some(1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn escape_user() {
+        assert_eq!(super::escape("al\"ice\\\n"), "al\\\"ice\\\\\\u000a");
+    }
+}
@@ -1,41 +1,466 @@
-use pam::Authenticator;
-use users::get_current_username;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(feature = "pam")]
+mod pam;
+#[cfg(feature = "shadow")]
+mod shadow;
+
+use crate::secret::SecretBuffer;
+
+#[cfg(not(any(test, feature = "pam", feature = "shadow")))]
+compile_error!("at least one of the pam and shadow features is required, mock can't lock anything");
+
+use serde::Deserialize;
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+use users::{get_current_username, get_user_by_name};
+
+use std::sync::{mpsc, Arc};
+use std::{error, fmt, io, str, thread};
+
+/// Set to 1 to allow the mock backend, which accepts a fixed password for anybody.
+const MOCK_OPT_IN: &str = "WAYLOCK_INSECURE_MOCK_AUTH";
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownBackend(String),
+    BackendDisabled(Backend),
+    NoUsername,
+    MockNotAllowed,
+    /// The PAM service couldn't be started.
+    #[cfg(feature = "pam")]
+    Pam(String, pam_sys::PamReturnCode),
+    Shadow(io::Error),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::UnknownBackend(_)
+            | Self::BackendDisabled(_)
+            | Self::NoUsername
+            | Self::MockNotAllowed => None,
+            #[cfg(feature = "pam")]
+            Self::Pam(..) => None,
+            Self::Shadow(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownBackend(name) => {
+                write!(f, "unknown auth backend \"{}\", must be one of pam, shadow or mock", name)
+            }
+            Self::BackendDisabled(backend) => {
+                write!(f, "auth backend {} is not enabled in this build", backend)
+            }
+            Self::NoUsername => write!(f, "failed to get the current username"),
+            Self::MockNotAllowed => write!(
+                f,
+                "the mock auth backend accepts a fixed password, set {}=1 to use it anyway",
+                MOCK_OPT_IN
+            ),
+            #[cfg(feature = "pam")]
+            Self::Pam(service, code) => {
+                write!(f, "failed to start PAM service \"{}\": {}", service, code)
+            }
+            Self::Shadow(err) => write!(f, "failed to open the shadow file: {}", err),
+        }
+    }
+}
+
+/// The result of an authentication attempt.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// The credentials were checked and are wrong.
+    Rejected,
+    /// The credentials couldn't be checked, e.g. because a service or device isn't available or
+    /// the conversation was aborted.
+    Unavailable,
+}
+
+/// Checks the credentials of a user. Implementations are called from worker threads and may
+/// block for as long as they need.
+pub trait Authenticator: Send + Sync {
+    /// Authenticate `user`, asking for any input needed through `conversation`. `session_owner` is
+    /// true if `user` is the one whose session is locked, only their account may be acted on
+    /// beyond checking it, e.g. by renewing credentials or changing an expired password.
+    fn authenticate(
+        &self,
+        user: &str,
+        session_owner: bool,
+        conversation: &mut dyn Conversation,
+    ) -> Outcome;
+}
+
+/// The available `Authenticator` implementations, each behind a cargo feature of the same name.
+/// The mock backend is only for testing, and refused unless explicitly allowed at runtime.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Pam,
+    Shadow,
+    Mock,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        if cfg!(feature = "pam") {
+            Self::Pam
+        } else {
+            Self::Shadow
+        }
+    }
+}
+
+impl str::FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pam" => Ok(Self::Pam),
+            "shadow" => Ok(Self::Shadow),
+            "mock" => Ok(Self::Mock),
+            _ => Err(Error::UnknownBackend(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pam => write!(f, "pam"),
+            Self::Shadow => write!(f, "shadow"),
+            Self::Mock => write!(f, "mock"),
+        }
+    }
+}
+
+/// Receives the messages of an authentication conversation.
+pub trait Conversation {
+    /// Ask the user for a response, `echo` is false for secrets such as passwords. Returning
+    /// `None` aborts the conversation.
+    fn prompt(&mut self, echo: bool, message: &str) -> Option<SecretBuffer>;
+    fn info(&mut self, message: &str);
+    fn error(&mut self, message: &str);
+}
+
+/// A question of the authenticator, e.g. for a one-time code after the password.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prompt {
+    pub message: String,
+    /// True if the response isn't secret, so it may be shown while typing it.
+    pub echo: bool,
+}
+
+/// A message of the authenticator that needs no response, e.g. to touch a security key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Notice {
+    Info(String),
+    Error(String),
+}
+
+pub enum AuthEvent {
+    /// The authenticator is blocked until the prompt is answered through the responder returned
+    /// by `LockAuth::spawn_check`.
+    Prompt(Prompt),
+    Notice(Notice),
+    /// The attempt is over.
+    Done(Outcome),
+}
+
+/// An event of the authentication attempt started with `LockAuth::spawn_check`.
+pub struct AuthMessage {
+    pub id: u64,
+    pub event: AuthEvent,
+}
+
+/// Forwards an authentication conversation running on a worker thread to the event loop.
+struct ChannelConversation {
+    id: u64,
+    /// Answers the first password prompt, as it was typed before submitting.
+    password: Option<SecretBuffer>,
+    sender: Sender<AuthMessage>,
+    /// None if the conversation runs without user input, prompts abort it.
+    responses: Option<mpsc::Receiver<SecretBuffer>>,
+}
+
+impl ChannelConversation {
+    fn send(&self, event: AuthEvent) -> bool {
+        self.sender.send(AuthMessage { id: self.id, event }).is_ok()
+    }
+}
+
+impl Conversation for ChannelConversation {
+    fn prompt(&mut self, echo: bool, message: &str) -> Option<SecretBuffer> {
+        if !echo {
+            if let Some(password) = self.password.take() {
+                return Some(password);
+            }
+        }
+
+        let responses = self.responses.as_ref()?;
+        if !self.send(AuthEvent::Prompt(Prompt { message: message.to_owned(), echo })) {
+            return None;
+        }
+        // Fails once the lock screen gives up on this attempt and drops the responder
+        responses.recv().ok()
+    }
+
+    fn info(&mut self, message: &str) {
+        self.send(AuthEvent::Notice(Notice::Info(message.to_owned())));
+    }
+
+    fn error(&mut self, message: &str) {
+        self.send(AuthEvent::Notice(Notice::Error(message.to_owned())));
+    }
+}
+
+/// The outcome of attempt `current` once the timeout started for attempt `timed_out` expires, or
+/// `None` if that attempt is already over. A module that doesn't answer in time may just be slow,
+/// so the credentials count as unchecked rather than wrong.
+pub fn timeout_outcome(timed_out: u64, current: u64) -> Option<Outcome> {
+    if timed_out == current {
+        Some(Outcome::Unavailable)
+    } else {
+        None
+    }
+}
+
+/// Returns true if `user` may unlock another user's session, either by name or by being a member
+/// of one of `groups`.
+pub fn is_allowed(user: &str, users: &[String], groups: &[String]) -> bool {
+    if users.iter().any(|allowed| allowed == user) {
+        return true;
+    }
+    if groups.is_empty() {
+        return false;
+    }
+    get_user_by_name(user).and_then(|user| user.groups()).map_or(false, |user_groups| {
+        user_groups
+            .iter()
+            .any(|group| groups.iter().any(|allowed| group.name() == allowed.as_str()))
+    })
+}
 
 pub struct LockAuth {
     login: String,
+    backend: Box<dyn Authenticator>,
 }
 
 impl LockAuth {
-    pub fn new() -> Self {
+    /// Create a new authenticator for the current user. The PAM service is only used by the PAM
+    /// backend, which picks a default service if none is given.
+    #[cfg_attr(not(feature = "pam"), allow(unused_variables))]
+    pub fn new(backend: Backend, pam_service: Option<&str>) -> Result<Self, Error> {
         let login = get_current_username()
-            .unwrap_or_else(|| {
-                log::error!("Failed to get current username.");
-                panic!();
-            })
+            .ok_or(Error::NoUsername)?
             .into_string()
-            .unwrap_or_else(|_| {
-                log::error!("Failed to parse the current username.");
-                panic!();
-            });
-        Self { login }
-    }
-
-    /// Attempt to authenticate with PAM. Returns true on success, otherwise false.
-    pub fn check_password(&self, password: &str) -> bool {
-        let mut authenticator = match Authenticator::with_password("system-auth") {
-            Ok(authenticator) => authenticator,
-            Err(err) => {
-                log::error!("Failed to initialize PAM client: {}", err);
-                panic!();
+            .map_err(|_| Error::NoUsername)?;
+
+        let backend: Box<dyn Authenticator> = match backend {
+            #[cfg(feature = "pam")]
+            Backend::Pam => Box::new(pam::PamAuthenticator::new(pam_service, &login)?),
+            #[cfg(feature = "shadow")]
+            Backend::Shadow => Box::new(shadow::ShadowAuthenticator::new()?),
+            #[cfg(any(test, feature = "mock"))]
+            Backend::Mock => {
+                if std::env::var_os(MOCK_OPT_IN).map_or(true, |value| value != "1") {
+                    return Err(Error::MockNotAllowed);
+                }
+                Box::new(mock::MockAuthenticator::default())
             }
+            #[allow(unreachable_patterns, unreachable_code)]
+            backend => return Err(Error::BackendDisabled(backend)),
         };
-        authenticator.get_handler().set_credentials(&self.login, password);
-        match authenticator.authenticate() {
-            Ok(()) => true,
-            Err(err) => {
-                log::warn!("Authentication failure {}", err);
-                false
+
+        Ok(Self { login, backend })
+    }
+
+    /// The user whose session is locked.
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    /// Attempt to authenticate `user`, routing every message through `conversation`.
+    pub fn authenticate(&self, user: &str, conversation: &mut dyn Conversation) -> Outcome {
+        self.backend.authenticate(user, user == self.login, conversation)
+    }
+
+    /// Run `authenticate` for `user` on a worker thread so that PAM can't block the event loop.
+    /// Events of the conversation are sent through `sender` tagged with `id`, the receiver is
+    /// responsible for discarding events of attempts it has given up on. Prompts after the first
+    /// password prompt are answered through the returned responder, dropping it aborts the
+    /// attempt. A module that hangs can't be interrupted though, so the thread is returned as well
+    /// to tell when it's really done.
+    pub fn spawn_check(
+        self: &Arc<Self>,
+        id: u64,
+        user: String,
+        password: SecretBuffer,
+        sender: Sender<AuthMessage>,
+    ) -> io::Result<(mpsc::Sender<SecretBuffer>, thread::JoinHandle<()>)> {
+        let (responder, responses) = mpsc::channel();
+        let thread = self.spawn(
+            user,
+            ChannelConversation {
+                id,
+                password: Some(password),
+                sender,
+                responses: Some(responses),
+            },
+        )?;
+        Ok((responder, thread))
+    }
+
+    /// Like `spawn_check` for the current user, but for services that authenticate without any
+    /// typed input such as a fingerprint reader. Prompts abort the attempt, info and error
+    /// messages are still sent.
+    pub fn spawn_passive(
+        self: &Arc<Self>,
+        id: u64,
+        sender: Sender<AuthMessage>,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        let conversation = ChannelConversation { id, password: None, sender, responses: None };
+        self.spawn(self.login.clone(), conversation)
+    }
+
+    fn spawn(
+        self: &Arc<Self>,
+        user: String,
+        mut conversation: ChannelConversation,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        let auth = Arc::clone(self);
+        thread::Builder::new().name("waylock-auth".into()).spawn(move || {
+            let outcome = auth.authenticate(&user, &mut conversation);
+            // The event loop is gone if this fails, so there's nobody left to tell.
+            conversation.send(AuthEvent::Done(outcome));
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockAuthenticator;
+    use super::{AuthEvent, Authenticator, ChannelConversation, Conversation, Outcome, Prompt};
+    use crate::secret::SecretBuffer;
+    use smithay_client_toolkit::reexports::calloop::channel;
+
+    use std::{io, sync::mpsc};
+
+    /// Answers prompts from a fixed list and records everything it was sent.
+    struct Script {
+        responses: Vec<&'static str>,
+        prompts: Vec<String>,
+    }
+
+    impl Conversation for Script {
+        fn prompt(&mut self, _echo: bool, message: &str) -> Option<SecretBuffer> {
+            self.prompts.push(message.to_owned());
+            if self.responses.is_empty() {
+                return None;
+            }
+            let mut response = SecretBuffer::new();
+            for ch in self.responses.remove(0).chars() {
+                response.push(ch);
             }
+            Some(response)
         }
+
+        fn info(&mut self, _message: &str) {}
+
+        fn error(&mut self, _message: &str) {}
+    }
+
+    fn check(responses: Vec<&'static str>) -> Outcome {
+        let mut script = Script { responses, prompts: Vec::new() };
+        let outcome = MockAuthenticator::new("hunter2").authenticate("alice", true, &mut script);
+        assert_eq!(script.prompts, ["Password: "]);
+        outcome
+    }
+
+    #[test]
+    fn mock_accepts_password() {
+        assert_eq!(check(vec!["hunter2"]), Outcome::Success);
+    }
+
+    #[test]
+    fn mock_rejects_wrong_password() {
+        assert_eq!(check(vec!["hunter3"]), Outcome::Rejected);
+    }
+
+    #[test]
+    fn mock_rejects_aborted_conversation() {
+        assert_eq!(check(vec![]), Outcome::Unavailable);
+    }
+
+    #[test]
+    fn channel_forwards_prompts() {
+        let (sender, events) = channel::channel();
+        let (responder, responses) = mpsc::channel();
+        let mut password = SecretBuffer::new();
+        password.push('a');
+        let mut conversation = ChannelConversation {
+            id: 1,
+            password: Some(password),
+            sender,
+            responses: Some(responses),
+        };
+
+        // The password answers the first hidden prompt without asking again
+        assert!(conversation.prompt(false, "Password: ").is_some());
+        assert!(events.try_recv().is_err());
+
+        responder.send(SecretBuffer::new()).unwrap();
+        assert!(conversation.prompt(true, "Code: ").is_some());
+        let message = events.try_recv().unwrap();
+        assert_eq!(message.id, 1);
+        assert!(matches!(
+            message.event,
+            AuthEvent::Prompt(Prompt { message, echo: true }) if message == "Code: "
+        ));
+    }
+
+    #[test]
+    fn timeout_is_unavailable() {
+        assert_eq!(super::timeout_outcome(3, 3), Some(Outcome::Unavailable));
+        // The timer of an attempt that's already over fires late
+        assert_eq!(super::timeout_outcome(2, 3), None);
+    }
+
+    #[test]
+    fn allowed_by_name() {
+        let users = ["alice".to_owned()];
+        assert!(super::is_allowed("alice", &users, &[]));
+        assert!(!super::is_allowed("bob", &users, &[]));
+    }
+
+    #[test]
+    fn allowed_by_group() {
+        // root is a member of the root group everywhere, but never of a group that doesn't exist
+        assert!(super::is_allowed("root", &[], &["root".to_owned()]));
+        assert!(!super::is_allowed("root", &[], &["waylock-nonexistent".to_owned()]));
+    }
+
+    #[test]
+    fn error_source() {
+        use std::error::Error;
+        let err = super::Error::Shadow(io::ErrorKind::PermissionDenied.into());
+        assert!(err.source().map_or(false, |source| source.is::<io::Error>()));
+    }
+
+    #[test]
+    fn mock_needs_opt_in() {
+        std::env::remove_var(super::MOCK_OPT_IN);
+        let result = super::LockAuth::new(super::Backend::Mock, None);
+        assert!(matches!(result, Err(super::Error::MockNotAllowed)));
+    }
+
+    #[test]
+    fn backend_from_str() {
+        assert_eq!("pam".parse::<super::Backend>().ok(), Some(super::Backend::Pam));
+        assert!(matches!("ldap".parse::<super::Backend>(), Err(super::Error::UnknownBackend(_))));
     }
 }
@@ -0,0 +1,39 @@
+use super::{Authenticator, Conversation, Outcome};
+
+/// Password accepted by the mock backend when selected through the config.
+const MOCK_PASSWORD: &str = "waylock";
+
+/// Accepts a single fixed password for any user. Useful for testing the lock screen without
+/// libpam or a real account, never enable it in production builds. Even when built in, it's only
+/// used with WAYLOCK_INSECURE_MOCK_AUTH=1 set.
+pub struct MockAuthenticator {
+    password: String,
+}
+
+impl MockAuthenticator {
+    pub fn new(password: &str) -> Self {
+        Self { password: password.to_owned() }
+    }
+}
+
+impl Default for MockAuthenticator {
+    fn default() -> Self {
+        Self::new(MOCK_PASSWORD)
+    }
+}
+
+impl Authenticator for MockAuthenticator {
+    fn authenticate(
+        &self,
+        user: &str,
+        _session_owner: bool,
+        conversation: &mut dyn Conversation,
+    ) -> Outcome {
+        log::warn!("Authenticating {} with the mock backend", user);
+        match conversation.prompt(false, "Password: ") {
+            Some(password) if password.as_str() == self.password => Outcome::Success,
+            Some(_) => Outcome::Rejected,
+            None => Outcome::Unavailable,
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use libc::{c_int, c_void, calloc, free, strdup};
+use pam_sys::{
+    PamConversation, PamFlag, PamHandle, PamMessage, PamMessageStyle, PamResponse, PamReturnCode,
+};
+
+use std::ffi::CStr;
+use std::path::Path;
+use std::{mem, ptr};
+
+use super::{Authenticator, Conversation, Error, Outcome};
+use crate::secret::SecretBuffer;
+
+/// Services tried in order when no PAM service is configured.
+const DEFAULT_SERVICES: &[&str] = &["waylock", "login", "system-auth"];
+
+/// Directories PAM reads service files from. Distributions shipping their defaults in /usr only
+/// use the latter two.
+const SERVICE_DIRS: &[&str] = &["/etc/pam.d", "/usr/lib/pam.d", "/usr/etc/pam.d"];
+
+/// Linux-PAM never sends more messages than this in a single call.
+const PAM_MAX_NUM_MSG: c_int = 32;
+
+pub struct PamAuthenticator {
+    service: String,
+}
+
+impl PamAuthenticator {
+    /// Create a new authenticator using the given PAM service, or the first installed service
+    /// of `DEFAULT_SERVICES` if none is given. The service is started once for `login` right
+    /// away, so that a broken PAM setup is reported now rather than on the first attempt.
+    pub fn new(service: Option<&str>, login: &str) -> Result<Self, Error> {
+        let service = match service {
+            Some(service) => service.to_owned(),
+            None => match find_service(SERVICE_DIRS, DEFAULT_SERVICES) {
+                Some(service) => service.to_owned(),
+                None => {
+                    log::warn!(
+                        "None of the PAM services {:?} are installed, PAM will fall back to \"other\"",
+                        DEFAULT_SERVICES
+                    );
+                    DEFAULT_SERVICES[0].to_owned()
+                }
+            },
+        };
+        log::info!("Using PAM service \"{}\"", service);
+
+        if let Err(code) = Transaction::start(&service, login, &mut Silent) {
+            return Err(Error::Pam(service, code));
+        }
+        Ok(Self { service })
+    }
+}
+
+/// Aborts every prompt, for transactions that are ended without authenticating.
+struct Silent;
+
+impl Conversation for Silent {
+    fn prompt(&mut self, _echo: bool, _message: &str) -> Option<SecretBuffer> {
+        None
+    }
+
+    fn info(&mut self, _message: &str) {}
+
+    fn error(&mut self, _message: &str) {}
+}
+
+impl Authenticator for PamAuthenticator {
+    fn authenticate(
+        &self,
+        user: &str,
+        session_owner: bool,
+        conversation: &mut dyn Conversation,
+    ) -> Outcome {
+        let mut transaction = match Transaction::start(&self.service, user, conversation) {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                log::error!("Failed to initialize PAM client: {}", err);
+                return Outcome::Unavailable;
+            }
+        };
+
+        if let Err(err) = transaction.authenticate() {
+            log::warn!("Authentication failure {}", err);
+            return match err {
+                PamReturnCode::AUTH_ERR
+                | PamReturnCode::MAXTRIES
+                | PamReturnCode::USER_UNKNOWN
+                | PamReturnCode::PERM_DENIED => Outcome::Rejected,
+                // Modules report missing devices or services, and time outs, as anything else
+                _ => Outcome::Unavailable,
+            };
+        }
+
+        validate(&mut transaction, user, session_owner)
+    }
+}
+
+/// The steps of a transaction after authenticating, which act on the account of the user.
+trait Account {
+    fn acct_mgmt(&mut self) -> Result<(), PamReturnCode>;
+    fn chauthtok(&mut self) -> Result<(), PamReturnCode>;
+    fn setcred(&mut self, flag: PamFlag) -> Result<(), PamReturnCode>;
+}
+
+/// Check that the account of the authenticated `user` may be used. Only the session owner gets
+/// to change an expired password and have their credentials refreshed, any other user merely
+/// unlocks someone else's session and fails if their password has expired.
+fn validate(account: &mut impl Account, user: &str, session_owner: bool) -> Outcome {
+    match account.acct_mgmt() {
+        Ok(()) => {}
+        // The new password is asked for through the conversation like any other prompt
+        Err(PamReturnCode::NEW_AUTHTOK_REQD) if session_owner => {
+            log::info!("Password of {} has expired, changing it", user);
+            if let Err(err) = account.chauthtok() {
+                log::warn!("Failed to change the expired password of {}: {}", user, err);
+                return Outcome::Rejected;
+            }
+        }
+        Err(PamReturnCode::NEW_AUTHTOK_REQD) => {
+            log::warn!("Password of {} has expired, it has to be changed to unlock", user);
+            return Outcome::Rejected;
+        }
+        Err(err) => {
+            log::warn!("Account of {} is not valid: {}", user, err);
+            return Outcome::Rejected;
+        }
+    }
+
+    // Renews Kerberos or AFS tickets, which doesn't stop us from unlocking if it fails
+    if session_owner {
+        if let Err(err) = account.setcred(PamFlag::REFRESH_CRED) {
+            log::warn!("Failed to refresh the credentials of {}: {}", user, err);
+        }
+    }
+    Outcome::Success
+}
+
+/// Returns the first of `services` that has a service file in any of `dirs`.
+fn find_service<'a>(dirs: &[&str], services: &[&'a str]) -> Option<&'a str> {
+    services
+        .iter()
+        .copied()
+        .find(|service| dirs.iter().any(|dir| Path::new(dir).join(service).is_file()))
+}
+
+/// A PAM transaction for a single user. All messages of the transaction are routed through the
+/// conversation it was started with, which is borrowed for as long as the transaction lives.
+struct Transaction<'a> {
+    handle: &'a mut PamHandle,
+    // PAM only gets a thin pointer, so it points at this fat one instead of the conversation. It
+    // is boxed to keep its address stable when the transaction is moved.
+    #[allow(clippy::redundant_allocation)]
+    _conversation: Box<&'a mut dyn Conversation>,
+    status: PamReturnCode,
+}
+
+impl<'a> Transaction<'a> {
+    fn start(
+        service: &str,
+        user: &str,
+        conversation: &'a mut dyn Conversation,
+    ) -> Result<Self, PamReturnCode> {
+        let mut conversation = Box::new(conversation);
+        // PAM keeps its own copy of this struct, only the data pointer has to stay valid.
+        let conv = PamConversation {
+            conv: Some(converse),
+            data_ptr: &mut *conversation as *mut &mut dyn Conversation as *mut c_void,
+        };
+        let mut handle: *mut PamHandle = ptr::null_mut();
+        match pam_sys::start(service, Some(user), &conv, &mut handle) {
+            PamReturnCode::SUCCESS => Ok(Self {
+                handle: unsafe { &mut *handle },
+                _conversation: conversation,
+                status: PamReturnCode::SUCCESS,
+            }),
+            code => Err(code),
+        }
+    }
+
+    fn authenticate(&mut self) -> Result<(), PamReturnCode> {
+        let code = pam_sys::authenticate(self.handle, PamFlag::NONE);
+        self.check(code)
+    }
+
+    fn check(&mut self, code: PamReturnCode) -> Result<(), PamReturnCode> {
+        self.status = code;
+        match code {
+            PamReturnCode::SUCCESS => Ok(()),
+            code => Err(code),
+        }
+    }
+}
+
+impl<'a> Account for Transaction<'a> {
+    fn acct_mgmt(&mut self) -> Result<(), PamReturnCode> {
+        let code = pam_sys::acct_mgmt(self.handle, PamFlag::NONE);
+        self.check(code)
+    }
+
+    /// Change an expired password, asking for the old and new passwords as needed.
+    fn chauthtok(&mut self) -> Result<(), PamReturnCode> {
+        let code = pam_sys::chauthtok(self.handle, PamFlag::CHANGE_EXPIRED_AUTHTOK);
+        self.check(code)
+    }
+
+    fn setcred(&mut self, flag: PamFlag) -> Result<(), PamReturnCode> {
+        let code = pam_sys::setcred(self.handle, flag);
+        self.check(code)
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        pam_sys::end(self.handle, self.status);
+    }
+}
+
+extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *mut PamMessage,
+    out_resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || num_msg > PAM_MAX_NUM_MSG {
+        return PamReturnCode::CONV_ERR as c_int;
+    }
+
+    // PAM takes ownership of the responses and frees them with free(3)
+    let resp =
+        unsafe { calloc(num_msg as usize, mem::size_of::<PamResponse>()) as *mut PamResponse };
+    if resp.is_null() {
+        return PamReturnCode::BUF_ERR as c_int;
+    }
+
+    let conversation = unsafe { &mut **(appdata_ptr as *mut &mut dyn Conversation) };
+
+    for i in 0..num_msg as usize {
+        let (style, text) = unsafe {
+            let m = &**msg.add(i);
+            (m.msg_style, CStr::from_ptr(m.msg).to_string_lossy())
+        };
+
+        let reply = match PamMessageStyle::from(style) {
+            PamMessageStyle::PROMPT_ECHO_OFF => conversation.prompt(false, &text),
+            PamMessageStyle::PROMPT_ECHO_ON => conversation.prompt(true, &text),
+            PamMessageStyle::TEXT_INFO => {
+                conversation.info(&text);
+                continue;
+            }
+            PamMessageStyle::ERROR_MSG => {
+                conversation.error(&text);
+                continue;
+            }
+        };
+
+        match reply {
+            Some(reply) => unsafe { (*resp.add(i)).resp = strdup(reply.as_c_str().as_ptr()) },
+            // The conversation was aborted
+            None => {
+                unsafe { free_responses(resp, i) };
+                return PamReturnCode::CONV_ERR as c_int;
+            }
+        }
+    }
+
+    unsafe { *out_resp = resp };
+    PamReturnCode::SUCCESS as c_int
+}
+
+/// Free the first `count` responses as well as the array holding them.
+unsafe fn free_responses(resp: *mut PamResponse, count: usize) {
+    for i in 0..count {
+        let r = &mut *resp.add(i);
+        if !r.resp.is_null() {
+            free(r.resp as *mut c_void);
+        }
+    }
+    free(resp as *mut c_void);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Account, Outcome};
+    use pam_sys::{PamFlag, PamReturnCode};
+    use std::{env, fs, process};
+
+    /// Records the steps taken on an account whose check returns `status`.
+    struct FakeAccount {
+        status: PamReturnCode,
+        calls: Vec<&'static str>,
+    }
+
+    impl Account for FakeAccount {
+        fn acct_mgmt(&mut self) -> Result<(), PamReturnCode> {
+            self.calls.push("acct_mgmt");
+            match self.status {
+                PamReturnCode::SUCCESS => Ok(()),
+                code => Err(code),
+            }
+        }
+
+        fn chauthtok(&mut self) -> Result<(), PamReturnCode> {
+            self.calls.push("chauthtok");
+            Ok(())
+        }
+
+        fn setcred(&mut self, _flag: PamFlag) -> Result<(), PamReturnCode> {
+            self.calls.push("setcred");
+            Ok(())
+        }
+    }
+
+    fn validate(status: PamReturnCode, session_owner: bool) -> (Outcome, Vec<&'static str>) {
+        let mut account = FakeAccount { status, calls: Vec::new() };
+        let outcome = super::validate(&mut account, "alice", session_owner);
+        (outcome, account.calls)
+    }
+
+    #[test]
+    fn owner_refreshes_credentials() {
+        let (outcome, calls) = validate(PamReturnCode::SUCCESS, true);
+        assert_eq!(outcome, Outcome::Success);
+        assert_eq!(calls, ["acct_mgmt", "setcred"]);
+    }
+
+    #[test]
+    fn owner_changes_expired_password() {
+        let (outcome, calls) = validate(PamReturnCode::NEW_AUTHTOK_REQD, true);
+        assert_eq!(outcome, Outcome::Success);
+        assert_eq!(calls, ["acct_mgmt", "chauthtok", "setcred"]);
+    }
+
+    #[test]
+    fn other_user_only_checks_account() {
+        let (outcome, calls) = validate(PamReturnCode::SUCCESS, false);
+        assert_eq!(outcome, Outcome::Success);
+        assert_eq!(calls, ["acct_mgmt"]);
+    }
+
+    #[test]
+    fn other_user_with_expired_password_fails() {
+        let (outcome, calls) = validate(PamReturnCode::NEW_AUTHTOK_REQD, false);
+        assert_eq!(outcome, Outcome::Rejected);
+        assert_eq!(calls, ["acct_mgmt"]);
+    }
+
+    #[test]
+    fn invalid_account_fails() {
+        let (outcome, calls) = validate(PamReturnCode::ACCT_EXPIRED, true);
+        assert_eq!(outcome, Outcome::Rejected);
+        assert_eq!(calls, ["acct_mgmt"]);
+    }
+
+    #[test]
+    fn find_service_respects_order() {
+        let dir = env::temp_dir().join(format!("waylock-pam-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("login"), "").unwrap();
+        fs::write(dir.join("system-auth"), "").unwrap();
+
+        let dirs = [dir.to_str().unwrap()];
+        assert_eq!(super::find_service(&dirs, super::DEFAULT_SERVICES), Some("login"));
+        assert_eq!(super::find_service(&dirs, &["waylock"]), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
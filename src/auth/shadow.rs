@@ -0,0 +1,123 @@
+use libc::c_char;
+
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::sync::Mutex;
+
+use super::{Authenticator, Conversation, Error, Outcome};
+
+const SHADOW_PATH: &str = "/etc/shadow";
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt(key: *const c_char, salt: *const c_char) -> *mut c_char;
+}
+
+/// crypt(3) returns a pointer to static storage.
+static CRYPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Checks passwords against the crypt(3) hashes in /etc/shadow, for minimal systems without PAM.
+/// Reading the shadow file usually requires waylock to be installed setgid shadow.
+pub struct ShadowAuthenticator;
+
+impl ShadowAuthenticator {
+    pub fn new() -> Result<Self, Error> {
+        // Fail early rather than on the first attempt if we aren't allowed to read it
+        fs::File::open(SHADOW_PATH).map_err(Error::Shadow)?;
+        Ok(Self)
+    }
+}
+
+impl Authenticator for ShadowAuthenticator {
+    /// Only checks the password, so there's nothing that only the session owner may do.
+    fn authenticate(
+        &self,
+        user: &str,
+        _session_owner: bool,
+        conversation: &mut dyn Conversation,
+    ) -> Outcome {
+        let password = match conversation.prompt(false, "Password: ") {
+            Some(password) => password,
+            None => return Outcome::Unavailable,
+        };
+
+        // Read on every attempt so that a password changed while locked is picked up
+        let shadow = match fs::read_to_string(SHADOW_PATH) {
+            Ok(shadow) => shadow,
+            Err(err) => {
+                log::error!("Failed to read {}: {}", SHADOW_PATH, err);
+                return Outcome::Unavailable;
+            }
+        };
+
+        match find_hash(&shadow, user) {
+            Some(hash) if verify(password.as_c_str(), hash) => Outcome::Success,
+            Some(_) => Outcome::Rejected,
+            None => {
+                log::warn!("No shadow entry for {}", user);
+                Outcome::Rejected
+            }
+        }
+    }
+}
+
+/// Returns the password hash of `user` from the contents of a shadow file.
+fn find_hash<'a>(shadow: &'a str, user: &str) -> Option<&'a str> {
+    shadow.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? == user {
+            fields.next()
+        } else {
+            None
+        }
+    })
+}
+
+/// Check `password` against a crypt(3) hash. Locked accounts and accounts without a password
+/// never match, a lock screen that unlocks on an empty password is useless.
+fn verify(password: &CStr, hash: &str) -> bool {
+    if hash.is_empty() || hash.starts_with('!') || hash.starts_with('*') {
+        return false;
+    }
+
+    let salt = match CString::new(hash) {
+        Ok(salt) => salt,
+        _ => return false,
+    };
+
+    let _guard = CRYPT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let result = unsafe { crypt(password.as_ptr(), salt.as_ptr()) };
+    if result.is_null() {
+        return false;
+    }
+    let result = unsafe { CStr::from_ptr(result) }.to_bytes();
+
+    // Compare in constant time
+    result.len() == hash.len()
+        && result.iter().zip(hash.as_bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    const HASH: &str = "$6$waylocksalt$xlmsGoGhEaAsKssGEHuSMJHGK8tAMwVUxTPajmkA7OrMF4aYjhFnLIxj3ky3UMDQEO9mply9IYwTMjeA7Gf8F/";
+
+    #[test]
+    fn find_hash() {
+        let shadow = format!("root:!:19000::::::\nalice:{}:19000:0:99999:7:::\n", HASH);
+        assert_eq!(super::find_hash(&shadow, "alice"), Some(HASH));
+        assert_eq!(super::find_hash(&shadow, "root"), Some("!"));
+        assert_eq!(super::find_hash(&shadow, "bob"), None);
+    }
+
+    #[test]
+    fn verify() {
+        let verify =
+            |password: &str, hash: &str| super::verify(&CString::new(password).unwrap(), hash);
+        assert!(verify("hunter2", HASH));
+        assert!(!verify("hunter3", HASH));
+        assert!(!verify("", ""));
+        assert!(!verify("hunter2", &format!("!{}", HASH)));
+    }
+}
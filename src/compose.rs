@@ -0,0 +1,117 @@
+use crate::secret::{self, SecretBuffer};
+
+use smithay_client_toolkit::seat::keyboard::Keysym;
+use xkbcommon::xkb::{self, compose};
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::{env, str};
+
+/// Longest text of a compose sequence in bytes, including the nul terminator.
+const MAX_TEXT_LEN: usize = 64;
+
+/// The outcome of feeding a key to the compose state.
+pub enum Composed {
+    /// The key isn't part of a sequence, or a sequence produced this keysym.
+    Keysym(Keysym),
+    /// A sequence produced text without a single keysym for it. It's typed into the password, so
+    /// it's kept in a secret as well.
+    Text(SecretBuffer),
+    /// The key was consumed by a sequence that's still in progress or was cancelled.
+    Pending,
+}
+
+/// Tracks compose sequences and dead keys using the compose table of the user's locale.
+pub struct Compose {
+    /// None if there's no compose table for the locale, keys are then passed through as is.
+    state: Option<compose::State>,
+}
+
+impl Compose {
+    pub fn new() -> Self {
+        let locale = locale();
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let state =
+            match compose::Table::new_from_locale(&context, &locale, compose::COMPILE_NO_FLAGS) {
+                Ok(table) => Some(compose::State::new(&table, compose::STATE_NO_FLAGS)),
+                Err(()) => {
+                    log::warn!("No compose table for locale {:?}, dead keys won't work", locale);
+                    None
+                }
+            };
+        Self { state }
+    }
+
+    pub fn feed(&mut self, keysym: Keysym) -> Composed {
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => return Composed::Keysym(keysym),
+        };
+
+        // Modifiers and the like are never part of a sequence
+        if matches!(state.feed(keysym), compose::FeedResult::Ignored) {
+            return Composed::Keysym(keysym);
+        }
+
+        match state.status() {
+            compose::Status::Nothing => Composed::Keysym(keysym),
+            compose::Status::Composing => Composed::Pending,
+            compose::Status::Composed => {
+                let composed = match state.keysym() {
+                    Some(keysym) if keysym.key_char().is_some() => Composed::Keysym(keysym),
+                    _ => text(state).map_or(Composed::Pending, Composed::Text),
+                };
+                state.reset();
+                composed
+            }
+            // Like other clients, drop the key that didn't fit the sequence
+            compose::Status::Cancelled => {
+                state.reset();
+                Composed::Pending
+            }
+        }
+    }
+
+    /// Abandon any sequence in progress.
+    pub fn reset(&mut self) {
+        if let Some(state) = &mut self.state {
+            state.reset();
+        }
+    }
+}
+
+/// The text of a finished sequence. `compose::State::utf8` would leave copies of it in memory, so
+/// it's read into a buffer that is wiped afterwards.
+fn text(state: &compose::State) -> Option<SecretBuffer> {
+    let mut buffer = [0_u8; MAX_TEXT_LEN];
+    let len = unsafe {
+        xkb::ffi::compose::xkb_compose_state_get_utf8(
+            state.get_raw_ptr(),
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+        )
+    };
+
+    let mut text = SecretBuffer::new();
+    // A length beyond the buffer means the text was truncated
+    let utf8 = match usize::try_from(len) {
+        Ok(len) if len < MAX_TEXT_LEN => str::from_utf8(&buffer[..len]).ok(),
+        _ => None,
+    };
+    let complete = utf8.map_or(false, |utf8| utf8.chars().all(|ch| text.push(ch)));
+    secret::wipe_bytes(&mut buffer);
+    if !complete {
+        log::warn!("Ignoring composed text that is too long");
+        return None;
+    }
+    (!text.as_str().is_empty()).then(|| text)
+}
+
+/// The locale that determines the compose table, following the precedence of setlocale(3).
+fn locale() -> OsString {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(env::var_os)
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_else(|| "C".into())
+}
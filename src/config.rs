@@ -1,6 +1,11 @@
-use serde::Deserialize;
+use crate::auth::Backend;
+use crate::keys::KeyCombo;
 
+use serde::{Deserialize, Deserializer};
+
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{env, error, fmt, fs, io};
 
 #[derive(Debug)]
@@ -50,7 +55,21 @@ impl fmt::Display for ConfigError {
 #[derive(Deserialize)]
 pub struct Config {
     pub fail_command: Option<String>,
+    pub sandbox: Option<bool>,
+    pub unlock_command: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub auth_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_key_combo")]
+    pub layout_switch_key: Option<KeyCombo>,
+    pub auth_backend: Option<Backend>,
+    pub pam_service: Option<String>,
+    pub fingerprint_service: Option<String>,
+    pub allowed_unlock_users: Option<Vec<String>>,
+    pub allowed_unlock_groups: Option<Vec<String>>,
     pub colors: Option<Colors>,
+    pub lockout: Option<Lockout>,
+    #[serde(default, deserialize_with = "deserialize_bindings")]
+    pub bindings: Option<Vec<(KeyCombo, String)>>,
 }
 
 #[derive(Deserialize)]
@@ -59,6 +78,57 @@ pub struct Colors {
     pub input_color: Option<u32>,
     pub wait_color: Option<u32>,
     pub fail_color: Option<u32>,
+    pub indicator_color: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub fail_timeout: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+pub struct Lockout {
+    pub threshold: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub delay: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max_delay: Option<Duration>,
+}
+
+/// Durations are written in the same human readable format accepted on the command line, e.g. "2s".
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| humantime::parse_duration(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Key combinations are written as on the command line, e.g. "Ctrl+Alt+space".
+fn deserialize_key_combo<'de, D>(deserializer: D) -> Result<Option<KeyCombo>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Bindings map key combinations to commands, e.g. `XF86AudioMute = "pactl ..."`.
+fn deserialize_bindings<'de, D>(
+    deserializer: D,
+) -> Result<Option<Vec<(KeyCombo, String)>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<BTreeMap<String, String>>::deserialize(deserializer)?
+        .map(|bindings| {
+            bindings
+                .into_iter()
+                .map(|(keys, command)| {
+                    keys.parse().map(|keys| (keys, command)).map_err(serde::de::Error::custom)
+                })
+                .collect()
+        })
+        .transpose()
 }
 
 impl Config {
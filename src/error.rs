@@ -0,0 +1,109 @@
+use crate::auth;
+
+use smithay_client_toolkit::reexports::calloop;
+use wayland_client::globals::{BindError, GlobalError};
+use wayland_client::ConnectError;
+
+use std::{error, fmt};
+
+/// Errors that make waylock give up, each reported with its own exit code.
+#[derive(Debug)]
+pub enum Error {
+    /// No compositor to connect to, e.g. because WAYLAND_DISPLAY isn't set.
+    Connect(ConnectError),
+    Globals(GlobalError),
+    /// The compositor lacks a global we can't do without, such as wl_shm.
+    Bind(BindError),
+    EventLoop(calloop::Error),
+    /// The compositor doesn't implement ext-session-lock-v1.
+    SessionLockUnsupported,
+    /// The compositor refused to lock the session, e.g. because another lock screen is running.
+    LockRefused,
+    /// The configured auth backend can't be used.
+    Auth(auth::Error),
+    /// The sandbox was requested along with PAM, whose setuid helpers it stops from working.
+    SandboxWithPam,
+}
+
+impl Error {
+    /// The exit code to report this error with, as documented in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Globals(_) | Self::Bind(_) | Self::EventLoop(_) | Self::SandboxWithPam => 1,
+            Self::SessionLockUnsupported => 2,
+            Self::LockRefused => 3,
+            Self::Auth(_) => 4,
+            Self::Connect(_) => 5,
+        }
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(err: ConnectError) -> Self {
+        Self::Connect(err)
+    }
+}
+
+impl From<GlobalError> for Error {
+    fn from(err: GlobalError) -> Self {
+        Self::Globals(err)
+    }
+}
+
+impl From<BindError> for Error {
+    fn from(err: BindError) -> Self {
+        Self::Bind(err)
+    }
+}
+
+impl From<calloop::Error> for Error {
+    fn from(err: calloop::Error) -> Self {
+        Self::EventLoop(err)
+    }
+}
+
+impl<T> From<calloop::InsertError<T>> for Error {
+    fn from(err: calloop::InsertError<T>) -> Self {
+        Self::EventLoop(err.error)
+    }
+}
+
+impl From<auth::Error> for Error {
+    fn from(err: auth::Error) -> Self {
+        Self::Auth(err)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Connect(err) => Some(err),
+            Self::Globals(err) => Some(err),
+            Self::Bind(err) => Some(err),
+            Self::EventLoop(err) => Some(err),
+            Self::SessionLockUnsupported | Self::LockRefused | Self::SandboxWithPam => None,
+            Self::Auth(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(err) => write!(f, "failed to connect to the compositor: {}", err),
+            Self::Globals(err) => write!(f, "failed to get the compositor's globals: {}", err),
+            Self::Bind(err) => write!(f, "compositor is missing a required global: {}", err),
+            Self::EventLoop(err) => write!(f, "event loop error: {}", err),
+            Self::SessionLockUnsupported => {
+                write!(f, "compositor doesn't support ext-session-lock-v1")
+            }
+            Self::LockRefused => write!(f, "compositor refused to lock the session"),
+            Self::Auth(err) => write!(f, "failed to initialize authentication: {}", err),
+            Self::SandboxWithPam => write!(
+                f,
+                "--sandbox can't be used with PAM, use the shadow backend without a fingerprint \
+                 service"
+            ),
+        }
+    }
+}
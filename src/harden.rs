@@ -0,0 +1,39 @@
+use std::io;
+
+/// Protect the passwords held by this process from other processes of the same user. Failures are
+/// logged rather than fatal, as the lock screen works without any of this.
+///
+/// No new privileges are only enabled if `no_new_privs` is set, as it also applies to the
+/// commands we spawn and stops setuid helpers such as PAM's unix_chkpwd from working.
+pub fn harden(no_new_privs: bool) {
+    // Also keeps processes of the same user from attaching with ptrace
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        log::error!("Failed to disable core dumps: {}", io::Error::last_os_error());
+    }
+
+    lock_memory();
+
+    if no_new_privs && unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        log::error!("Failed to set no new privileges: {}", io::Error::last_os_error());
+    }
+}
+
+/// Keep all of our memory from being swapped out.
+fn lock_memory() {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        log::error!("Failed to get the locked memory limit: {}", io::Error::last_os_error());
+        return;
+    }
+
+    // Once locked, every later allocation counts against the limit and fails when it's reached.
+    // Root isn't subject to the limit.
+    if limit.rlim_cur != libc::RLIM_INFINITY && unsafe { libc::geteuid() } != 0 {
+        log::info!("Locked memory is limited, only locking the password buffer");
+        return;
+    }
+
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        log::error!("Failed to lock memory: {}", io::Error::last_os_error());
+    }
+}
@@ -0,0 +1,113 @@
+use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+use xkbcommon::xkb;
+
+use std::{error, fmt, str};
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownKey(String),
+    UnknownModifier(String),
+}
+
+impl error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey(name) => write!(f, "unknown key \"{}\"", name),
+            Self::UnknownModifier(name) => {
+                write!(f, "unknown modifier \"{}\", must be one of Ctrl, Alt, Shift or Super", name)
+            }
+        }
+    }
+}
+
+/// A key and the modifiers held with it, written like "Ctrl+Alt+space". Keys are named by their
+/// keysym names, as listed in xkbcommon-keysyms.h without the XKB_KEY_ prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub keysym: Keysym,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl KeyCombo {
+    /// Whether pressing `keysym` with `modifiers` held is this combination. Modifiers have to
+    /// match exactly, letters match in either case as Shift changes them.
+    pub fn matches(&self, keysym: Keysym, modifiers: &Modifiers) -> bool {
+        self.ctrl == modifiers.ctrl
+            && self.alt == modifiers.alt
+            && self.shift == modifiers.shift
+            && self.logo == modifiers.logo
+            && same_key(self.keysym, keysym)
+    }
+}
+
+fn same_key(a: Keysym, b: Keysym) -> bool {
+    match (a.key_char(), b.key_char()) {
+        (Some(a), Some(b)) => a.to_lowercase().eq(b.to_lowercase()),
+        _ => a == b,
+    }
+}
+
+impl str::FromStr for KeyCombo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The key comes last, so that "Ctrl+plus" and the like need no escaping
+        let (modifiers, key) = match s.rfind('+') {
+            Some(i) if i + 1 < s.len() => (&s[..i], &s[i + 1..]),
+            _ => ("", s),
+        };
+
+        let mut keysym = xkb::keysym_from_name(key, xkb::KEYSYM_NO_FLAGS);
+        if keysym == Keysym::NoSymbol {
+            keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+        }
+        if keysym == Keysym::NoSymbol {
+            return Err(Error::UnknownKey(key.to_owned()));
+        }
+
+        let mut combo = Self { keysym, ctrl: false, alt: false, shift: false, logo: false };
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => combo.ctrl = true,
+                "alt" | "mod1" => combo.alt = true,
+                "shift" => combo.shift = true,
+                "super" | "logo" | "mod4" => combo.logo = true,
+                _ => return Err(Error::UnknownModifier(modifier.to_owned())),
+            }
+        }
+        Ok(combo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyCombo;
+    use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+
+    #[test]
+    fn parse() {
+        let combo: KeyCombo = "Ctrl+super+space".parse().unwrap();
+        assert_eq!(
+            combo,
+            KeyCombo { keysym: Keysym::space, ctrl: true, alt: false, shift: false, logo: true }
+        );
+        assert_eq!("plus".parse::<KeyCombo>().unwrap().keysym, Keysym::plus);
+        assert_eq!("Alt+plus".parse::<KeyCombo>().unwrap().keysym, Keysym::plus);
+        assert!("Hyper+space".parse::<KeyCombo>().is_err());
+        assert!("Ctrl+nokey".parse::<KeyCombo>().is_err());
+    }
+
+    #[test]
+    fn matches() {
+        let combo: KeyCombo = "Shift+Super+a".parse().unwrap();
+        let modifiers = Modifiers { shift: true, logo: true, ..Modifiers::default() };
+        assert!(combo.matches(Keysym::A, &modifiers));
+        assert!(!combo.matches(Keysym::b, &modifiers));
+        assert!(!combo.matches(Keysym::A, &Modifiers { shift: true, ..Modifiers::default() }));
+    }
+}
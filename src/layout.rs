@@ -0,0 +1,140 @@
+use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+use xkbcommon::xkb;
+
+/// The keyboard layouts of the compositor's keymap. Compositors don't handle their own layout
+/// switching bindings while locked, so layouts can be overridden on our side, in which case key
+/// presses are translated with our own copy of the keymap.
+pub struct Layouts {
+    context: xkb::Context,
+    keymap: Option<xkb::Keymap>,
+    state: Option<xkb::State>,
+    /// Follows the keys held on the keyboard, for the modifiers that `Modifiers` leaves out such
+    /// as AltGr.
+    held: Option<xkb::State>,
+    /// The layout active in the compositor.
+    active: u32,
+    /// The layout chosen while locked, if it differs from the active one.
+    selected: Option<u32>,
+}
+
+impl Layouts {
+    pub fn new() -> Self {
+        Self {
+            context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            keymap: None,
+            state: None,
+            held: None,
+            active: 0,
+            selected: None,
+        }
+    }
+
+    /// Replace the keymap with the one sent by the compositor, dropping any override.
+    pub fn set_keymap(&mut self, keymap: &str) {
+        self.keymap = xkb::Keymap::new_from_string(
+            &self.context,
+            keymap.to_owned(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        if self.keymap.is_none() {
+            log::warn!("Failed to compile the keymap, layouts can't be switched");
+        }
+        self.state = self.keymap.as_ref().map(xkb::State::new);
+        self.held = self.keymap.as_ref().map(xkb::State::new);
+        self.selected = None;
+    }
+
+    /// Start over with only the keys `raw_codes` held, e.g. those reported on keyboard enter.
+    pub fn set_held(&mut self, raw_codes: &[u32]) {
+        self.held = self.keymap.as_ref().map(xkb::State::new);
+        for &raw_code in raw_codes {
+            self.update_key(raw_code, true);
+        }
+    }
+
+    /// Follow a key press or release.
+    pub fn update_key(&mut self, raw_code: u32, pressed: bool) {
+        let layout = self.current();
+        let held = match &mut self.held {
+            Some(held) => held,
+            None => return,
+        };
+
+        // Which modifier a key sets depends on the layout, e.g. right Alt is AltGr in some only
+        let depressed = held.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+        let latched = held.serialize_mods(xkb::STATE_MODS_LATCHED);
+        let locked = held.serialize_mods(xkb::STATE_MODS_LOCKED);
+        held.update_mask(depressed, latched, locked, 0, 0, layout);
+        let direction = if pressed { xkb::KeyDirection::Down } else { xkb::KeyDirection::Up };
+        held.update_key(xkb::Keycode::new(raw_code + 8), direction);
+    }
+
+    /// Follow a layout change in the compositor, which takes precedence over our own.
+    pub fn set_active(&mut self, layout: u32) {
+        if layout != self.active {
+            self.active = layout;
+            self.selected = None;
+        }
+    }
+
+    /// Switch to the next layout, returning whether there was another one to switch to.
+    pub fn cycle(&mut self) -> bool {
+        let count = self.keymap.as_ref().map_or(0, |keymap| keymap.num_layouts());
+        if count < 2 {
+            return false;
+        }
+        let next = (self.current() + 1) % count;
+        self.selected = if next == self.active { None } else { Some(next) };
+        true
+    }
+
+    fn current(&self) -> u32 {
+        self.selected.unwrap_or(self.active)
+    }
+
+    /// The name of the current layout, e.g. "English (US)".
+    pub fn name(&self) -> Option<&str> {
+        let keymap = self.keymap.as_ref()?;
+        let name = keymap.layout_get_name(self.current());
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    /// The keysym of the key with the evdev code `raw_code` in the selected layout, or None if
+    /// the compositor's keysym applies. Shift levels are resolved from `modifiers` along with the
+    /// held modifier keys, so that e.g. AltGr selects the third level.
+    pub fn keysym(&mut self, raw_code: u32, modifiers: &Modifiers) -> Option<Keysym> {
+        let layout = self.selected?;
+        let keymap = self.keymap.as_ref()?;
+        let state = self.state.as_mut()?;
+        let held = self.held.as_ref()?;
+
+        let mask = |mods: &[(&str, bool)]| {
+            mods.iter()
+                .filter(|(_, on)| *on)
+                .map(|(name, _)| keymap.mod_get_index(name))
+                .filter(|&index| index < 32)
+                .fold(0, |mask, index| mask | 1 << index)
+        };
+        let depressed = mask(&[
+            (xkb::MOD_NAME_SHIFT, modifiers.shift),
+            (xkb::MOD_NAME_CTRL, modifiers.ctrl),
+            (xkb::MOD_NAME_ALT, modifiers.alt),
+            (xkb::MOD_NAME_LOGO, modifiers.logo),
+        ]) | held.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+        let latched = held.serialize_mods(xkb::STATE_MODS_LATCHED);
+        // Lock keys pressed before we got the keyboard are only known to the compositor
+        let locked = mask(&[
+            (xkb::MOD_NAME_CAPS, modifiers.caps_lock),
+            (xkb::MOD_NAME_NUM, modifiers.num_lock),
+        ]);
+        state.update_mask(depressed, latched, locked, 0, 0, layout);
+
+        // Wayland key codes are evdev codes, xkb's are offset by 8
+        Some(state.key_get_one_sym(xkb::Keycode::new(raw_code + 8)))
+    }
+}
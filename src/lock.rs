@@ -1,8 +1,152 @@
+use std::time::{Duration, SystemTime};
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum LockState {
     Init,
     Input,
     Wait,
+    /// The authenticator asked for another response, which is typed like the password.
+    Prompt,
     Fail,
+    /// Input is refused after too many failed attempts, until the given time.
+    Lockout {
+        until: SystemTime,
+    },
     Success,
 }
+
+impl LockState {
+    /// Returns true if the lock screen may move from this state to `next`. `Success` is reached
+    /// by waiting on the authenticator, or from any state but a lockout by a fingerprint, which is
+    /// checked independently of typed input but refused during a lockout just like a password. Answering a prompt goes back to waiting on the same attempt.
+    /// A lockout is entered instead of failing once there were too many failed attempts, on
+    /// startup if one is still active, or when a rejected fingerprint starts or extends one. A
+    /// failure is only left once the fail timeout expires, through `after_fail_timeout`.
+    pub fn can_transition(self, next: Self) -> bool {
+        use LockState::*;
+        matches!(
+            (self, next),
+            (Init, Input)
+                | (Init, Lockout { .. })
+                | (Init, Success)
+                | (Input, Wait)
+                | (Input, Lockout { .. })
+                | (Input, Success)
+                | (Wait, Prompt)
+                | (Wait, Fail)
+                | (Wait, Lockout { .. })
+                | (Wait, Success)
+                | (Prompt, Wait)
+                | (Prompt, Success)
+                | (Fail, Lockout { .. })
+                | (Fail, Success)
+                | (Lockout { .. }, Input)
+                | (Lockout { .. }, Lockout { .. })
+        )
+    }
+
+    /// Returns false while key presses are ignored, which is until a failure or lockout expires.
+    pub fn accepts_input(self) -> bool {
+        !matches!(self, LockState::Fail | LockState::Lockout { .. })
+    }
+
+    /// Returns how long a lockout still lasts at `now`, zero once it expired. None if this isn't
+    /// a lockout.
+    pub fn lockout_remaining(self, now: SystemTime) -> Option<Duration> {
+        match self {
+            LockState::Lockout { until } => Some(until.duration_since(now).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    /// The state to enter once the fail timeout or lockout has expired.
+    pub fn after_fail_timeout(self) -> Self {
+        match self {
+            LockState::Fail | LockState::Lockout { .. } => LockState::Input,
+            state => state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockState::{self, *};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const LOCKOUT: LockState = Lockout { until: UNIX_EPOCH };
+
+    macro_rules! test {
+        ($name: ident: $from: expr => $to: expr, $result: expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!($from.can_transition($to), $result);
+            }
+        };
+    }
+
+    test!(init_to_input: Init => Input, true);
+    test!(init_to_success: Init => Success, true);
+    test!(input_to_wait: Input => Wait, true);
+    test!(input_to_success: Input => Success, true);
+    test!(input_to_lockout: Input => LOCKOUT, true);
+    test!(wait_to_fail: Wait => Fail, true);
+    test!(wait_to_success: Wait => Success, true);
+    test!(wait_to_input: Wait => Input, false);
+    test!(wait_to_prompt: Wait => Prompt, true);
+    test!(prompt_to_wait: Prompt => Wait, true);
+    test!(prompt_to_success: Prompt => Success, true);
+    test!(prompt_to_lockout: Prompt => LOCKOUT, false);
+    test!(prompt_to_input: Prompt => Input, false);
+    test!(fail_to_input: Fail => Input, false);
+    test!(fail_to_success: Fail => Success, true);
+    test!(fail_to_lockout: Fail => LOCKOUT, true);
+    test!(fail_to_wait: Fail => Wait, false);
+    test!(success_to_input: Success => Input, false);
+    test!(success_to_lockout: Success => LOCKOUT, false);
+    test!(wait_to_lockout: Wait => LOCKOUT, true);
+    test!(lockout_to_input: LOCKOUT => Input, true);
+    test!(lockout_to_wait: LOCKOUT => Wait, false);
+    test!(lockout_to_success: LOCKOUT => Success, false);
+    test!(lockout_to_lockout: LOCKOUT => LOCKOUT, true);
+    test!(lockout_to_fail: LOCKOUT => Fail, false);
+
+    #[test]
+    fn fail_timeout_returns_to_input() {
+        assert_eq!(Fail.after_fail_timeout(), Input);
+    }
+
+    #[test]
+    fn fail_refuses_input() {
+        // Typing neither goes into the password nor cuts the fail timeout short
+        assert!(!Fail.accepts_input());
+        assert!(!Fail.can_transition(Input));
+        assert!(Fail.after_fail_timeout().accepts_input());
+    }
+
+    #[test]
+    fn lockout_expiry_returns_to_input() {
+        assert_eq!(LOCKOUT.after_fail_timeout(), Input);
+    }
+
+    #[test]
+    fn lockout_remaining() {
+        let until = UNIX_EPOCH + Duration::from_secs(60);
+        let lockout = Lockout { until };
+        assert_eq!(
+            lockout.lockout_remaining(until - Duration::from_secs(15)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            lockout.lockout_remaining(until + Duration::from_secs(15)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(Input.lockout_remaining(until), None);
+    }
+
+    #[test]
+    fn fail_timeout_keeps_other_states() {
+        for state in [Init, Input, Wait, Prompt, Success] {
+            assert_eq!(state.after_fail_timeout(), state);
+        }
+    }
+}
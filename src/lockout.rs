@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+
+/// Delays never grow beyond this, even without a configured cap.
+const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long input is refused after repeated failed attempts.
+#[derive(Copy, Clone, Debug)]
+pub struct Policy {
+    /// Number of consecutive failures before input is refused, 0 disables the lockout.
+    pub threshold: u32,
+    /// Delay after reaching the threshold, doubled with each further failure.
+    pub delay: Duration,
+    /// Upper bound for the delay, if any.
+    pub max_delay: Option<Duration>,
+}
+
+impl Policy {
+    /// Returns the delay imposed after `failures` consecutive failed attempts.
+    pub fn delay(&self, failures: u32) -> Duration {
+        if self.threshold == 0 || failures < self.threshold {
+            return Duration::ZERO;
+        }
+
+        let factor = 1u32.checked_shl(failures - self.threshold).unwrap_or(u32::MAX);
+        let delay = self.delay.checked_mul(factor).unwrap_or(MAX_DELAY).min(MAX_DELAY);
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+}
+
+/// Counts consecutive failed attempts. The count is persisted so that killing and relaunching
+/// waylock doesn't reset it.
+pub struct Tracker {
+    path: Option<PathBuf>,
+    failures: u32,
+    last_failure: SystemTime,
+}
+
+impl Tracker {
+    /// Load the failure count from $XDG_RUNTIME_DIR/waylock/failures. Without a runtime dir the
+    /// count is only kept in memory.
+    pub fn new() -> Self {
+        let path = env::var_os("XDG_RUNTIME_DIR")
+            .map(|runtime_dir| Path::new(&runtime_dir).join("waylock/failures"));
+        if path.is_none() {
+            log::warn!("XDG_RUNTIME_DIR is not set, failed attempts won't persist across restarts");
+        }
+        Self::load(path)
+    }
+
+    fn load(path: Option<PathBuf>) -> Self {
+        let mut tracker = Self { path, failures: 0, last_failure: UNIX_EPOCH };
+        let contents = match &tracker.path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return tracker,
+                Err(err) => {
+                    log::error!("Failed to read {}: {}", path.display(), err);
+                    return tracker;
+                }
+            },
+            None => return tracker,
+        };
+
+        // "<failures> <unix time of the last failure>"
+        let mut fields = contents.split_whitespace().map(str::parse::<u64>);
+        match (fields.next(), fields.next()) {
+            (Some(Ok(failures)), Some(Ok(last_failure))) => {
+                tracker.failures = failures.min(u32::MAX.into()) as u32;
+                tracker.last_failure = UNIX_EPOCH + Duration::from_secs(last_failure);
+            }
+            _ => log::error!("Ignoring malformed failure count {:?}", contents),
+        }
+        tracker
+    }
+
+    /// The directory the failure count is saved in, if any.
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn record_failure(&mut self, now: SystemTime) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = now;
+        self.save();
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        if let Some(path) = &self.path {
+            if let Err(err) = fs::remove_file(path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    log::error!("Failed to remove {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    /// Returns until when input is refused under `policy`, None if the failures don't call for a
+    /// lockout at all. The time may have passed already.
+    pub fn until(&self, policy: &Policy) -> Option<SystemTime> {
+        let delay = policy.delay(self.failures);
+        if delay.is_zero() {
+            return None;
+        }
+        self.last_failure.checked_add(delay)
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        // Written to a temporary file first, so that a crash never leaves a truncated count
+        let last_failure = self.last_failure.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let tmp_path = path.with_extension("tmp");
+        let result = path
+            .parent()
+            .map_or(Ok(()), create_private_dir)
+            .and_then(|()| {
+                fs::write(&tmp_path, format!("{} {}\n", self.failures, last_failure.as_secs()))
+            })
+            .and_then(|()| fs::rename(&tmp_path, path));
+        if let Err(err) = result {
+            log::error!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Policy, Tracker};
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::{env, fs, process};
+
+    const POLICY: Policy = Policy {
+        threshold: 3,
+        delay: Duration::from_secs(10),
+        max_delay: Some(Duration::from_secs(60)),
+    };
+
+    #[test]
+    fn no_delay_below_threshold() {
+        assert_eq!(POLICY.delay(0), Duration::ZERO);
+        assert_eq!(POLICY.delay(2), Duration::ZERO);
+    }
+
+    #[test]
+    fn delay_doubles_up_to_cap() {
+        assert_eq!(POLICY.delay(3), Duration::from_secs(10));
+        assert_eq!(POLICY.delay(4), Duration::from_secs(20));
+        assert_eq!(POLICY.delay(5), Duration::from_secs(40));
+        assert_eq!(POLICY.delay(6), Duration::from_secs(60));
+        assert_eq!(POLICY.delay(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn uncapped_delay_saturates() {
+        let policy = Policy { max_delay: None, ..POLICY };
+        assert_eq!(policy.delay(u32::MAX), super::MAX_DELAY);
+    }
+
+    #[test]
+    fn threshold_zero_disables() {
+        let policy = Policy { threshold: 0, ..POLICY };
+        assert_eq!(policy.delay(100), Duration::ZERO);
+    }
+
+    #[test]
+    fn count_persists() {
+        let path = env::temp_dir().join(format!("waylock-lockout-{}/failures", process::id()));
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        let mut tracker = Tracker::load(Some(path.clone()));
+        for _ in 0..4 {
+            tracker.record_failure(now);
+        }
+
+        let tracker = Tracker::load(Some(path.clone()));
+        assert_eq!(tracker.failures(), 4);
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(tracker.until(&POLICY), Some(now + Duration::from_secs(20)));
+        assert_eq!(Tracker::load(None).until(&POLICY), None);
+
+        let mut tracker = tracker;
+        tracker.reset();
+        assert_eq!(Tracker::load(Some(path.clone())).failures(), 0);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
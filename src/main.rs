@@ -1,129 +1,344 @@
+mod audit;
 mod auth;
 mod color;
+mod compose;
 mod config;
+mod error;
+mod harden;
+mod keys;
+mod layout;
 mod lock;
+mod lockout;
 mod logger;
 mod options;
+mod pinpad;
+mod render;
+mod sandbox;
+mod seat;
+mod secret;
+mod surface;
 
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     output::{OutputHandler, OutputState},
     reexports::{
         calloop::{
+            channel,
             timer::{TimeoutAction, Timer},
-            EventLoop, LoopHandle,
+            EventLoop, LoopHandle, RegistrationToken,
         },
         calloop_wayland_source::WaylandSource,
+        protocols::wp::{
+            fractional_scale::v1::client::{
+                wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
+            },
+            viewporter::client::{wp_viewport, wp_viewporter},
+        },
     },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
-        keyboard::{KeyboardHandler, Keysym},
+        keyboard::{KeyboardHandler, Keymap, Keysym, Modifiers},
         pointer::{PointerEvent, PointerEventKind, PointerHandler},
+        touch::TouchHandler,
         Capability, SeatHandler, SeatState,
     },
     session_lock::{
         SessionLock, SessionLockHandler, SessionLockState, SessionLockSurface,
         SessionLockSurfaceConfigure,
     },
-    shm::{raw::RawPool, Shm, ShmHandler},
+    shm::{Shm, ShmHandler},
 };
-use std::process::Command;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use wayland_client::{
     globals::registry_queue_init,
     protocol::{
         wl_buffer, wl_keyboard, wl_output,
         wl_pointer::{self},
-        wl_seat, wl_shm, wl_surface,
+        wl_seat, wl_surface, wl_touch,
     },
-    Connection, QueueHandle,
+    Connection, Dispatch, QueueHandle,
 };
 
+use crate::error::Error;
+use crate::lock::LockState;
 use crate::options::Options;
+use crate::secret::SecretBuffer;
 
 struct AppData {
     loop_handle: LoopHandle<'static, Self>,
     conn: Connection,
     compositor_state: CompositorState,
-    keyboard: Option<wl_keyboard::WlKeyboard>,
     output_state: OutputState,
-    pointer: Option<wl_pointer::WlPointer>,
+    /// Input devices by seat, all of which may be used to unlock.
+    seats: Vec<seat::SeatInput>,
+    /// The seat the current input was typed on.
+    input_seat: Option<wl_seat::WlSeat>,
     registry_state: RegistryState,
     shm: Shm,
     seat_state: SeatState,
     session_lock_state: SessionLockState,
     session_lock: Option<SessionLock>,
-    lock_surfaces: Vec<SessionLockSurface>,
-    lock_surfaces_out: Vec<(SessionLockSurface, i32, i32)>,
+    /// One lock surface per output.
+    lock_surfaces: Vec<surface::LockSurface>,
+    /// Both are needed for fractional scaling, without them only integer scales are supported.
+    fractional_scale_manager: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+    viewporter: Option<wp_viewporter::WpViewporter>,
     options: Options,
-    lock_state: lock::LockState,
+    lock_state: LockState,
+    fail_timer: Option<RegistrationToken>,
+    lockout: lockout::Tracker,
+    lockout_timer: Option<RegistrationToken>,
+    audit: audit::AuditLog,
     color: u32,
-    passwd: String,
+    passwd: SecretBuffer,
+    /// The modifiers of the keyboard used last.
+    modifiers: Modifiers,
+    /// The name of the keyboard layout of the keyboard used last.
+    layout: Option<String>,
+    /// Another user to unlock as, typed into the username field while `editing_user` is set.
+    unlock_user: Option<String>,
+    editing_user: bool,
+    /// Set while showing a failed attempt that was typed with Caps Lock on.
+    caps_lock_hint: bool,
     exit: bool,
-    auth_hdl: auth::LockAuth,
+    /// Why we exited, if not because the session was unlocked.
+    error: Option<Error>,
+    /// Commands started by key bindings or on failure that haven't been reaped yet.
+    children: Vec<Child>,
+    reap_timer: Option<RegistrationToken>,
+    /// When each key binding last started its command, by its index in the options.
+    binding_runs: HashMap<usize, Instant>,
+    auth_hdl: Arc<auth::LockAuth>,
+    auth_sender: channel::Sender<auth::AuthMessage>,
+    attempt_ids: u64,
+    auth_attempt: u64,
+    /// Length of the password submitted with the current attempt, for the audit log.
+    auth_password_len: usize,
+    /// The user the current attempt authenticates, logged on unlock.
+    auth_user: String,
+    auth_timer: Option<RegistrationToken>,
+    auth_responder: Option<mpsc::Sender<SecretBuffer>>,
+    /// The prompt the typed input answers, while in `LockState::Prompt`.
+    auth_prompt: Option<auth::Prompt>,
+    /// The last info or error message of the current attempt.
+    auth_notice: Option<auth::Notice>,
+    /// The thread of the last attempt, which may outlive an attempt that timed out.
+    auth_thread: Option<thread::JoinHandle<()>>,
+    /// The attempt `auth_thread` runs.
+    auth_thread_attempt: u64,
+    /// Consecutive attempts that timed out with `auth_thread` still running.
+    auth_timeouts: u32,
+    /// The password of the current attempt while it waits for `auth_thread` to finish.
+    auth_queued: Option<SecretBuffer>,
+    fingerprint_hdl: Option<Arc<auth::LockAuth>>,
+    fingerprint_attempt: u64,
+    fingerprint_timer: Option<RegistrationToken>,
+    /// The thread of the last fingerprint attempt, which holds the reader until it's done.
+    fingerprint_thread: Option<thread::JoinHandle<()>>,
+    fingerprint_started: Instant,
+    /// Consecutive fingerprint attempts that ended right away without checking a fingerprint.
+    fingerprint_errors: u32,
+}
+
+/// A command started with `sh -c`, kept until it exits so that it can be reaped.
+struct Child {
+    process: process::Child,
+    /// The index of the key binding that started it, if any.
+    binding: Option<usize>,
 }
 
+/// The left mouse button, as defined in linux/input-event-codes.h.
+const BTN_LEFT: u32 = 0x110;
+
+/// Delay before a fingerprint attempt is started over, doubled with each consecutive error of the
+/// fingerprint service.
+const FINGERPRINT_BACKOFF: lockout::Policy = lockout::Policy {
+    threshold: 1,
+    delay: Duration::from_secs(1),
+    max_delay: Some(Duration::from_secs(60)),
+};
+
+/// A fingerprint attempt ending without a verdict sooner than this is an error of the service,
+/// rather than the reader giving up on waiting for a finger.
+const FINGERPRINT_MIN_ATTEMPT: Duration = Duration::from_secs(1);
+
+/// How often commands that are still running are checked for having exited.
+const REAP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A held key runs the command bound to it at most this often, and never while the previous run
+/// is still going.
+const BINDING_MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Fingerprint authentication is disabled after this many consecutive errors.
+const FINGERPRINT_MAX_ERRORS: u32 = 8;
+
+/// After this many consecutive attempts timed out while a module is stuck, the next one starts
+/// over with a fresh authentication handle instead of waiting for it.
+const AUTH_MAX_TIMEOUTS: u32 = 3;
+
 fn main() {
     //env_logger::init();
 
-    let conn = Connection::connect_to_env().expect("Error: ");
-    let (globals, event_queue) = registry_queue_init(&conn).unwrap();
-    let qh: QueueHandle<AppData> = event_queue.handle();
-    let mut event_loop: EventLoop<AppData> =
-        EventLoop::try_new().expect("Failed to initialize the event loop!");
+    let options = Options::new();
+    // Before connecting, so that nothing we receive or allocate later is left unprotected
+    if options.harden {
+        // PAM may rely on setuid helpers, which no new privileges would render useless
+        harden::harden(!options.uses_pam());
+    }
+
+    if let Err(err) = run(options) {
+        log::error!("{}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+/// Lock the session and run until it's unlocked.
+fn run(options: Options) -> Result<(), Error> {
+    // The sandbox would leave PAM unable to check any password, and the session locked for good
+    if options.sandbox && options.uses_pam() {
+        return Err(Error::SandboxWithPam);
+    }
+
+    let conn = Connection::connect_to_env()?;
+    let (globals, event_queue) = registry_queue_init(&conn)?;
+    let mut event_loop: EventLoop<AppData> = EventLoop::try_new()?;
 
     let qh: QueueHandle<AppData> = event_queue.handle();
 
+    // Resolve and check the PAM service once up front rather than on every attempt
+    let auth_hdl =
+        Arc::new(auth::LockAuth::new(options.auth_backend, options.pam_service.as_deref())?);
+    // Fingerprint readers are only supported through PAM
+    let fingerprint_hdl = options.fingerprint_service.as_deref().and_then(|service| {
+        auth::LockAuth::new(auth::Backend::Pam, Some(service))
+            .map_err(|err| log::error!("Fingerprint authentication disabled: {}", err))
+            .ok()
+            .map(Arc::new)
+    });
+    let (auth_sender, auth_channel) = channel::channel();
+
     let mut app_data = AppData {
         loop_handle: event_loop.handle(),
         conn,
-        compositor_state: CompositorState::bind(&globals, &qh).unwrap(),
-        keyboard: None,
-        pointer: None,
+        compositor_state: CompositorState::bind(&globals, &qh)?,
+        seats: Vec::new(),
+        input_seat: None,
         output_state: OutputState::new(&globals, &qh),
         registry_state: RegistryState::new(&globals),
         seat_state: SeatState::new(&globals, &qh),
-        shm: Shm::bind(&globals, &qh).unwrap(),
+        shm: Shm::bind(&globals, &qh)?,
         session_lock_state: SessionLockState::new(&globals, &qh),
         session_lock: None,
         lock_surfaces: Vec::new(),
-        lock_surfaces_out: Vec::new(),
-        options: Options::new(),
-        lock_state: lock::LockState::Init,
+        fractional_scale_manager: globals.bind(&qh, 1..=1, ()).ok(),
+        viewporter: globals.bind(&qh, 1..=1, ()).ok(),
+        options,
+        lock_state: LockState::Init,
+        fail_timer: None,
+        lockout: lockout::Tracker::new(),
+        lockout_timer: None,
+        audit: audit::AuditLog::new(),
         color: 0,
-        passwd: String::new(),
+        passwd: SecretBuffer::new(),
+        modifiers: Modifiers::default(),
+        layout: None,
+        unlock_user: None,
+        editing_user: false,
+        caps_lock_hint: false,
         exit: false,
-        auth_hdl: auth::LockAuth::new(),
+        error: None,
+        children: Vec::new(),
+        reap_timer: None,
+        binding_runs: HashMap::new(),
+        auth_hdl,
+        auth_sender,
+        attempt_ids: 0,
+        auth_attempt: 0,
+        auth_password_len: 0,
+        auth_user: String::new(),
+        auth_timer: None,
+        auth_responder: None,
+        auth_prompt: None,
+        auth_notice: None,
+        auth_thread: None,
+        auth_thread_attempt: 0,
+        auth_timeouts: 0,
+        auth_queued: None,
+        fingerprint_hdl,
+        fingerprint_attempt: 0,
+        fingerprint_timer: None,
+        fingerprint_thread: None,
+        fingerprint_started: Instant::now(),
+        fingerprint_errors: 0,
     };
 
     app_data.color = app_data.options.init_color;
 
-    app_data.session_lock =
-        Some(app_data.session_lock_state.lock(&qh).expect("ext-session-lock not supported"));
+    // A lockout from before a restart is still in effect
+    if let Some(lockout) = app_data.lockout_state() {
+        app_data.set_color(&qh, lockout);
+    }
+
+    let session_lock =
+        app_data.session_lock_state.lock(&qh).map_err(|_| Error::SessionLockUnsupported)?;
+
+    app_data.session_lock = Some(session_lock);
 
     // After locking the session, we're expected to create a lock surface for each output.
     // As soon as all lock surfaces are created, `SessionLockHandler::locked` will be called
-    // and the every surface receives a `SessionLockHandler::configure` call.
-    for output in app_data.output_state().outputs() {
-        let session_lock = app_data.session_lock.as_ref().unwrap();
-        let surface = app_data.compositor_state.create_surface(&qh);
-
-        // It's important to keep the `SessionLockSurface` returned here around, as the
-        // surface will be destroyed when the `SessionLockSurface` is dropped.
-        let lock_surface = session_lock.create_lock_surface(surface, &output, &qh);
-        app_data.lock_surfaces.push(lock_surface);
+    // and the every surface receives a `SessionLockHandler::configure` call. Outputs that show
+    // up later get theirs in `OutputHandler::new_output`.
+    let outputs: Vec<_> = app_data.output_state().outputs().collect();
+    for output in outputs {
+        app_data.create_lock_surface(&qh, output);
     }
 
-    WaylandSource::new(app_data.conn.clone(), event_queue).insert(event_loop.handle()).unwrap();
+    WaylandSource::new(app_data.conn.clone(), event_queue).insert(event_loop.handle())?;
+
+    // Events of authentication attempts running on worker threads
+    let auth_qh = qh.clone();
+    event_loop.handle().insert_source(auth_channel, move |event, _, app_data| {
+        if let channel::Event::Msg(message) = event {
+            app_data.handle_auth(&auth_qh, message);
+        }
+    })?;
+
+    // Everything is set up, so give up what we no longer need before handling any input. No
+    // authentication threads are running yet, so they all inherit the sandbox.
+    if app_data.options.sandbox {
+        let writable: Vec<_> =
+            app_data.lockout.dir().into_iter().chain(app_data.audit.dir()).collect();
+        sandbox::enter(&writable);
+    }
 
     loop {
-        event_loop.dispatch(None, &mut app_data).unwrap();
+        event_loop.dispatch(None, &mut app_data)?;
 
         if app_data.exit {
-            break;
+            return app_data.error.take().map_or(Ok(()), Err);
+        }
+    }
+}
+
+/// Returns true while `thread` is running, joining it once it's done.
+fn is_running(thread: &mut Option<thread::JoinHandle<()>>) -> bool {
+    if thread.as_ref().map_or(false, |thread| !thread.is_finished()) {
+        return true;
+    }
+    if let Some(thread) = thread.take() {
+        if thread.join().is_err() {
+            log::error!("Authentication thread panicked");
         }
     }
+    false
 }
 
 impl SeatHandler for AppData {
@@ -131,7 +346,9 @@ impl SeatHandler for AppData {
         &mut self.seat_state
     }
 
-    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        self.seat_input(&seat);
+    }
 
     fn new_capability(
         &mut self,
@@ -140,54 +357,85 @@ impl SeatHandler for AppData {
         seat: wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_none() {
-            let keyboard = self
-                .seat_state
-                .get_keyboard_with_repeat(
-                    qh,
-                    &seat,
-                    None,
-                    self.loop_handle.clone(),
-                    Box::new(|_state, _wl_kbd, _event| {
-                        //println!("Repeat: {:?} ", event);
-                    }),
-                )
-                .expect("Failed to create keyboard");
-
-            self.keyboard = Some(keyboard);
-        }
-
-        if capability == Capability::Pointer && self.pointer.is_none() {
+        let index = self.seat_input(&seat);
+
+        if capability == Capability::Keyboard && self.seats[index].keyboard.is_none() {
+            let keyboard = self.seat_state.get_keyboard_with_repeat(
+                qh,
+                &seat,
+                None,
+                self.loop_handle.clone(),
+                Box::new(|app_data: &mut AppData, wl_kbd, event| {
+                    app_data.repeat_key(wl_kbd, event)
+                }),
+            );
+
+            match keyboard {
+                Ok(keyboard) => self.seats[index].keyboard = Some(keyboard),
+                Err(err) => log::error!("Failed to create keyboard: {}", err),
+            }
+        }
+
+        if capability == Capability::Pointer && self.seats[index].pointer.is_none() {
             println!("Set pointer capability");
-            let pointer = self.seat_state.get_pointer(qh, &seat).expect("Failed to create pointer");
-            self.pointer = Some(pointer);
+            match self.seat_state.get_pointer(qh, &seat) {
+                Ok(pointer) => {
+                    pointer.set_cursor(0, None, 0, 0);
+                    self.seats[index].pointer = Some(pointer);
+                }
+                Err(err) => log::error!("Failed to create pointer: {}", err),
+            }
+        }
 
-            match &self.pointer {
-                Some(pointer) => pointer.set_cursor(0, None, 0, 0),
-                None => {}
+        if capability == Capability::Touch && self.seats[index].touch.is_none() {
+            match self.seat_state.get_touch(qh, &seat) {
+                Ok(touch) => self.seats[index].touch = Some(touch),
+                Err(err) => log::error!("Failed to create touch: {}", err),
             }
         }
+
+        // Gaining a keyboard may hide the PIN pad and gaining touch show it
+        self.redraw_all(qh);
     }
 
     fn remove_capability(
         &mut self,
         _conn: &Connection,
-        _: &QueueHandle<Self>,
-        _: wl_seat::WlSeat,
+        qh: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_some() {
-            println!("Unset keyboard capability");
-            self.keyboard.take().unwrap().release();
+        let seat = match self.seats.iter_mut().find(|input| input.seat == seat) {
+            Some(seat) => seat,
+            None => return,
+        };
+
+        match capability {
+            Capability::Keyboard => {
+                println!("Unset keyboard capability");
+                seat.release_keyboard();
+            }
+            Capability::Pointer => {
+                println!("Unset pointer capability");
+                seat.release_pointer();
+            }
+            Capability::Touch => seat.release_touch(),
+            _ => {}
         }
 
-        if capability == Capability::Pointer && self.pointer.is_some() {
-            println!("Unset pointer capability");
-            self.pointer.take().unwrap().release();
-        }
+        self.redraw_all(qh);
     }
 
-    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn remove_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(index) = self.seats.iter().position(|input| input.seat == seat) {
+            self.seats.remove(index).release();
+        }
+        if self.input_seat.as_ref() == Some(&seat) {
+            self.input_seat = None;
+        }
+
+        self.redraw_all(qh);
+    }
 }
 
 impl KeyboardHandler for AppData {
@@ -195,84 +443,120 @@ impl KeyboardHandler for AppData {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _surface: &wl_surface::WlSurface,
         _serial: u32,
-        _raw: &[u32],
+        raw: &[u32],
         _keysym: &[smithay_client_toolkit::seat::keyboard::Keysym],
     ) {
-        println!("enter keyboard")
+        println!("enter keyboard");
+        if let Some(index) = self.keyboard_seat(keyboard) {
+            self.seats[index].layouts.set_held(raw);
+        }
     }
 
     fn leave(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _surface: &wl_surface::WlSurface,
         _serial: u32,
     ) {
-        println!("leave keyboard")
+        println!("leave keyboard");
+        if let Some(index) = self.keyboard_seat(keyboard) {
+            self.seats[index].layouts.set_held(&[]);
+        }
     }
 
     fn press_key(
         &mut self,
         _conn: &Connection,
         qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
         event: smithay_client_toolkit::seat::keyboard::KeyEvent,
     ) {
-        let redraw = self.set_color(lock::LockState::Input);
-        if redraw {
-            self.redraw_all(qh);
+        let index = match self.keyboard_seat(keyboard) {
+            Some(index) => index,
+            None => return,
+        };
+        self.seats[index].repeat = None;
+        self.modifiers = self.seats[index].modifiers;
+
+        let keysym = self.seats[index]
+            .layouts
+            .keysym(event.raw_code, &self.modifiers)
+            .unwrap_or(event.keysym);
+        self.seats[index].layouts.update_key(event.raw_code, true);
+        // Bindings work regardless of the lock state and don't count as input
+        if self.run_binding(keysym) {
+            self.seats[index].repeat = Some((event.raw_code, self.lock_state));
+            return;
         }
 
-        match event.keysym {
-            Keysym::KP_Enter | Keysym::Return => {
-                let redraw = self.set_color(lock::LockState::Wait);
-                if redraw {
-                    self.redraw_all(qh);
-                }
+        if !self.accepts_input(qh) {
+            return;
+        }
 
-                if self.auth_hdl.check_password(self.passwd.as_str()) {
-                    self.set_color(lock::LockState::Success);
-                } else {
-                    println!("failure!");
-                    let redraw = self.set_color(lock::LockState::Fail);
-                    if redraw {
-                        self.redraw_all(qh);
-                    }
+        let seat = self.seats[index].seat.clone();
+        self.switch_input_seat(&seat);
 
-                    self.loop_handle
-                        .insert_source(
-                            Timer::from_duration(Duration::from_secs(5)),
-                            |_, _, app_data| {
-                                // Unlock the lock
-                                app_data.session_lock.take().unwrap().unlock();
-                                // Sync connection to make sure compostor receives destroy
-                                app_data.conn.roundtrip().unwrap();
-                                // Then we can exit
-                                app_data.exit = true;
-                                TimeoutAction::Drop
-                            },
-                        )
-                        .unwrap();
-                }
+        let redraw = self.set_color(qh, LockState::Input);
+        if redraw {
+            self.redraw_all(qh);
+        }
 
-                self.passwd.clear();
+        if self.options.layout_switch_key.matches(keysym, &self.modifiers) {
+            self.seats[index].compose.reset();
+            if self.seats[index].layouts.cycle() {
+                self.update_layout(qh, index);
             }
-            Keysym::Delete | Keysym::BackSpace => {
-                self.passwd.pop();
+            return;
+        }
+
+        // Ctrl+Alt+U switches to the username field to let another user unlock
+        if self.modifiers.ctrl && self.modifiers.alt && matches!(keysym, Keysym::u | Keysym::U) {
+            self.edit_unlock_user(qh);
+            return;
+        }
+
+        match keysym {
+            Keysym::KP_Enter | Keysym::Return => {
+                self.seats[index].compose.reset();
+                self.submit(qh);
             }
             Keysym::Escape => {
+                self.seats[index].compose.reset();
                 self.passwd.clear();
-            }
-            _ => {
-                if let Some(ch) = event.keysym.key_char() {
-                    self.passwd.push(ch)
+                // Go back to unlocking as the current user
+                self.unlock_user = None;
+                if mem::take(&mut self.editing_user) {
+                    self.redraw_all(qh);
                 }
+                // Abort a conversation the user can't or doesn't want to answer
+                if self.lock_state == LockState::Prompt {
+                    self.answer_prompt(qh, false);
+                }
+            }
+            // Shortcuts bypass compose sequences
+            _ if self.modifiers.ctrl => {
+                self.edit(keysym);
+                self.seats[index].repeat = Some((event.raw_code, self.lock_state));
             }
+            _ => match self.seats[index].compose.feed(keysym) {
+                compose::Composed::Keysym(composed) => {
+                    self.edit(composed);
+                    // Only keys typed as is repeat, not the result of a sequence
+                    if composed == keysym {
+                        self.seats[index].repeat = Some((event.raw_code, self.lock_state));
+                    }
+                }
+                compose::Composed::Text(text) => {
+                    text.as_str().chars().for_each(|ch| self.push_char(ch))
+                }
+                compose::Composed::Pending => {}
+            },
         }
     }
 
@@ -280,23 +564,56 @@ impl KeyboardHandler for AppData {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
-        _event: smithay_client_toolkit::seat::keyboard::KeyEvent,
+        event: smithay_client_toolkit::seat::keyboard::KeyEvent,
     ) {
-        //println!("release!");
+        if let Some(index) = self.keyboard_seat(keyboard) {
+            let seat = &mut self.seats[index];
+            seat.layouts.update_key(event.raw_code, false);
+            if matches!(seat.repeat, Some((raw_code, _)) if raw_code == event.raw_code) {
+                seat.repeat = None;
+            }
+        }
     }
 
     fn update_modifiers(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        qh: &QueueHandle<Self>,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
-        _modifiers: smithay_client_toolkit::seat::keyboard::Modifiers,
-        _layout: u32,
+        modifiers: smithay_client_toolkit::seat::keyboard::Modifiers,
+        layout: u32,
+    ) {
+        let index = match self.keyboard_seat(keyboard) {
+            Some(index) => index,
+            None => return,
+        };
+        self.seats[index].modifiers = modifiers;
+
+        // The indicators show the keyboard that changed last
+        let indicators = self.indicators();
+        self.modifiers = modifiers;
+        if self.indicators() != indicators {
+            self.redraw_all(qh);
+        }
+
+        self.seats[index].layouts.set_active(layout);
+        self.update_layout(qh, index);
+    }
+
+    fn update_keymap(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keymap: Keymap<'_>,
     ) {
-        //println!("update modifiers!")
+        if let Some(index) = self.keyboard_seat(keyboard) {
+            self.seats[index].layouts.set_keymap(&keymap.as_string());
+            self.update_layout(qh, index);
+        }
     }
 }
 
@@ -304,7 +621,7 @@ impl PointerHandler for AppData {
     fn pointer_frame(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         _pointer: &wl_pointer::WlPointer,
         events: &[PointerEvent],
     ) {
@@ -323,6 +640,15 @@ impl PointerHandler for AppData {
                 Motion { .. } => {}
                 Press { button, .. } => {
                     println!("Press {:x} @ {:?}", button, event.position);
+                    // The PIN pad can be clicked as well when there's no keyboard
+                    let seat = self
+                        .seats
+                        .iter()
+                        .find(|input| input.pointer.as_ref() == Some(_pointer))
+                        .map(|input| input.seat.clone());
+                    if let (BTN_LEFT, Some(seat)) = (button, seat) {
+                        self.tap(qh, &seat, &event.surface, event.position);
+                    }
                 }
                 Release { button, .. } => {
                     println!("Release {:x} @ {:?}", button, event.position);
@@ -335,9 +661,75 @@ impl PointerHandler for AppData {
     }
 }
 
+impl TouchHandler for AppData {
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        touch: &wl_touch::WlTouch,
+        _serial: u32,
+        _time: u32,
+        surface: wl_surface::WlSurface,
+        _id: i32,
+        position: (f64, f64),
+    ) {
+        let seat = match self.seats.iter().find(|input| input.touch.as_ref() == Some(touch)) {
+            Some(input) => input.seat.clone(),
+            None => return,
+        };
+        self.tap(qh, &seat, &surface, position);
+    }
+
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _serial: u32,
+        _time: u32,
+        _id: i32,
+    ) {
+    }
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _time: u32,
+        _id: i32,
+        _position: (f64, f64),
+    ) {
+    }
+
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _id: i32,
+        _major: f64,
+        _minor: f64,
+    ) {
+    }
+
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _id: i32,
+        _orientation: f64,
+    ) {
+    }
+
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _touch: &wl_touch::WlTouch) {}
+}
+
 impl SessionLockHandler for AppData {
-    fn locked(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _session_lock: SessionLock) {
+    fn locked(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, _session_lock: SessionLock) {
         println!("Locked");
+        self.start_fingerprint(qh);
     }
 
     fn finished(
@@ -346,7 +738,7 @@ impl SessionLockHandler for AppData {
         _qh: &QueueHandle<Self>,
         _session_lock: SessionLock,
     ) {
-        println!("Session could not be locked");
+        self.error = Some(Error::LockRefused);
         self.exit = true;
     }
 
@@ -360,8 +752,13 @@ impl SessionLockHandler for AppData {
     ) {
         println!("configure");
         let (width, height) = configure.new_size;
-        self.redraw(qh, &session_lock_surface, width as i32, height as i32);
-        self.lock_surfaces_out.push((session_lock_surface, width as i32, height as i32));
+        // Configured again whenever the output's mode changes
+        let index = match self.lock_surface(session_lock_surface.wl_surface()) {
+            Some(index) => index,
+            None => return,
+        };
+        self.lock_surfaces[index].configure(width, height);
+        self.redraw(qh, index);
     }
 }
 
@@ -369,10 +766,18 @@ impl CompositorHandler for AppData {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
+        let index = match self.lock_surface(surface) {
+            Some(index) => index,
+            None => return,
+        };
+        // The preferred fractional scale takes precedence
+        if self.lock_surfaces[index].fractional.is_none() {
+            self.set_scale(qh, index, new_factor.max(1) as u32 * surface::SCALE_DENOMINATOR);
+        }
     }
 
     fn transform_changed(
@@ -425,9 +830,10 @@ impl OutputHandler for AppData {
     fn new_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
+        self.create_lock_surface(qh, output);
     }
 
     fn update_output(
@@ -443,8 +849,10 @@ impl OutputHandler for AppData {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
+        // Dropping the lock surface destroys it
+        self.lock_surfaces.retain(|lock_surface| lock_surface.output != output);
     }
 }
 
@@ -462,99 +870,943 @@ impl ShmHandler for AppData {
 }
 
 impl AppData {
-    pub fn set_color(&mut self, state: lock::LockState) -> bool {
-        match self.lock_state {
-            lock::LockState::Init => {
-                if state != lock::LockState::Input {
-                    return false;
+    /// Move to the given lock state if the transition is allowed, updating the color and
+    /// triggering any side effects of the new state. Returns true if a redraw is needed.
+    pub fn set_color(&mut self, qh: &QueueHandle<Self>, state: LockState) -> bool {
+        if !self.lock_state.can_transition(state) {
+            // After the fail timeout input is accepted again, but the initial color is shown
+            // until the next key press.
+            if state == LockState::Input
+                && self.lock_state == LockState::Input
+                && self.color != self.options.input_color
+            {
+                self.color = self.options.input_color;
+                return true;
+            }
+
+            return false;
+        }
+
+        self.lock_state = state;
+        match state {
+            LockState::Init => self.color = self.options.init_color,
+            LockState::Input => self.color = self.options.input_color,
+            LockState::Wait => self.color = self.options.wait_color,
+            LockState::Prompt => self.color = self.options.input_color,
+            LockState::Fail => {
+                self.color = self.options.fail_color;
+                self.run_fail_command();
+
+                // Never leave the failure state by unlocking, only return to accepting input.
+                let qh = qh.clone();
+                self.fail_timer = self.add_timer(self.options.fail_timeout, move |app_data| {
+                    app_data.fail_timer = None;
+                    if app_data.lock_state == LockState::Fail {
+                        app_data.return_to_input(&qh);
+                    }
+                });
+                // Nothing would end the failure state, so accept input right away
+                if self.fail_timer.is_none() {
+                    self.lock_state = self.lock_state.after_fail_timeout();
+                }
+            }
+            LockState::Lockout { .. } => {
+                self.color = self.options.fail_color;
+
+                let remaining = state.lockout_remaining(SystemTime::now()).unwrap_or_default();
+                log::warn!(
+                    "{} failed attempts, refusing input for {}",
+                    self.lockout.failures(),
+                    humantime::format_duration(remaining)
+                );
+
+                // Further failures extend the lockout, which starts the timer over
+                if let Some(token) = self.lockout_timer.take() {
+                    self.loop_handle.remove(token);
                 }
+                // Without the timer the lockout ends with the first key press after it expired
+                let qh = qh.clone();
+                self.lockout_timer = self.add_timer(remaining, move |app_data| {
+                    app_data.lockout_timer = None;
+                    if app_data.lock_state == state {
+                        app_data.return_to_input(&qh);
+                    }
+                });
+            }
+            LockState::Success => self.unlock(),
+        }
 
-                self.lock_state = state;
-                self.color = self.options.input_color;
+        true
+    }
+
+    /// Returns false while key presses are ignored, after a failure until the fail timeout
+    /// expires and during a lockout.
+    fn accepts_input(&mut self, qh: &QueueHandle<Self>) -> bool {
+        // Without its timer, an expired lockout ends with the next key press
+        if self.lockout_timer.is_none()
+            && self.lock_state.lockout_remaining(SystemTime::now()) == Some(Duration::ZERO)
+        {
+            self.return_to_input(qh);
+        }
+        self.lock_state.accepts_input()
+    }
+
+    /// Accept input again after a failure or lockout, showing the initial color until the next
+    /// key press.
+    fn return_to_input(&mut self, qh: &QueueHandle<Self>) {
+        self.lock_state = self.lock_state.after_fail_timeout();
+        self.color = self.options.init_color;
+        self.caps_lock_hint = false;
+        self.redraw_all(qh);
+    }
+
+    fn run_fail_command(&mut self) {
+        if let Some(command) = self.options.fail_command.clone() {
+            if let Err(err) = self.spawn_command(&command, None) {
+                log::warn!("Error executing fail command \"{}\": {}", command, err);
+            }
+        }
+    }
+
+    /// Run the command bound to `keysym` with the held modifiers, returning false if there is
+    /// none. Repeats of a held key are dropped while the command is still running or if it only
+    /// just started.
+    fn run_binding(&mut self, keysym: Keysym) -> bool {
+        let (index, command) = match self
+            .options
+            .bindings
+            .iter()
+            .enumerate()
+            .find(|(_, (keys, _))| keys.matches(keysym, &self.modifiers))
+        {
+            Some((index, (_, command))) => (index, command.clone()),
+            None => return false,
+        };
+
+        self.reap_children();
+        let recent =
+            self.binding_runs.get(&index).map_or(false, |run| run.elapsed() < BINDING_MIN_INTERVAL);
+        if recent || self.children.iter().any(|child| child.binding == Some(index)) {
+            return true;
+        }
+
+        self.binding_runs.insert(index, Instant::now());
+        if let Err(err) = self.spawn_command(&command, Some(index)) {
+            log::warn!("Error executing bound command \"{}\": {}", command, err);
+        }
+        true
+    }
+
+    /// Run `command` with `sh -c`, reaping it once it exits.
+    fn spawn_command(&mut self, command: &str, binding: Option<usize>) -> io::Result<()> {
+        let process = Command::new("sh").arg("-c").arg(command).spawn()?;
+        self.children.push(Child { process, binding });
+        self.reap_children();
+        Ok(())
+    }
+
+    /// Reap the commands that have exited, checking again later while any are still running.
+    fn reap_children(&mut self) {
+        self.children.retain_mut(|child| match child.process.try_wait() {
+            Ok(status) => status.is_none(),
+            Err(err) => {
+                log::warn!("Failed to wait for command: {}", err);
+                false
+            }
+        });
+
+        if !self.children.is_empty() && self.reap_timer.is_none() {
+            self.reap_timer = self.add_timer(REAP_INTERVAL, |app_data| {
+                app_data.reap_timer = None;
+                app_data.reap_children();
+            });
+        }
+    }
+
+    /// The lockout to enter if input is still refused after too many failed attempts.
+    fn lockout_state(&self) -> Option<LockState> {
+        let until = self.lockout.until(&self.options.lockout)?;
+        (until > SystemTime::now()).then(|| LockState::Lockout { until })
+    }
+
+    /// Log who unlocked and report the failed attempts since the previous unlock, in the log and to
+    /// the unlock command.
+    fn record_unlock(&self) {
+        if self.auth_user == self.auth_hdl.login() {
+            log::info!("Unlocked by {}", self.auth_user);
+        } else {
+            log::warn!("Unlocked by {} on behalf of {}", self.auth_user, self.auth_hdl.login());
+        }
+
+        let summary = match self.audit.record_unlock(SystemTime::now(), &self.auth_user) {
+            Some(summary) => summary,
+            None => return,
+        };
+        log::info!("{}", summary);
+
+        if let Some(command) = &self.options.unlock_command {
+            let result = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("WAYLOCK_FAILED_ATTEMPTS", summary.failures.to_string())
+                .env("WAYLOCK_SUMMARY", summary.to_string())
+                .spawn();
+            if let Err(err) = result {
+                log::warn!("Error executing unlock command \"{}\": {}", command, err);
             }
-            lock::LockState::Input => {
-                if state != lock::LockState::Wait {
-                    return false;
+        }
+    }
+
+    fn unlock(&mut self) {
+        self.record_unlock();
+        // Unlock once we're back in the event loop, or right away if that's not possible
+        if self.add_timer(Duration::ZERO, Self::unlock_session).is_none() {
+            self.unlock_session();
+        }
+    }
+
+    fn unlock_session(&mut self) {
+        // Unlock the lock
+        if let Some(session_lock) = self.session_lock.take() {
+            session_lock.unlock();
+        }
+        // Sync connection to make sure compostor receives destroy
+        if let Err(err) = self.conn.roundtrip() {
+            log::error!("Failed to sync with the compositor: {}", err);
+        }
+        // Then we can exit
+        self.exit = true;
+    }
+
+    /// Call `callback` once `duration` has passed. Returns None if the timer couldn't be added,
+    /// which is logged.
+    fn add_timer(
+        &self,
+        duration: Duration,
+        mut callback: impl FnMut(&mut Self) + 'static,
+    ) -> Option<RegistrationToken> {
+        let timer = Timer::from_duration(duration);
+        let result = self.loop_handle.insert_source(timer, move |_, _, app_data| {
+            callback(app_data);
+            TimeoutAction::Drop
+        });
+        match result {
+            Ok(token) => Some(token),
+            Err(err) => {
+                log::error!("Failed to add a timer: {}", err.error);
+                None
+            }
+        }
+    }
+
+    /// Hand the current password to the authenticator, or answer its pending prompt. Keys pressed
+    /// while waiting for the result go into a fresh buffer for the next attempt, unless the
+    /// authenticator asks for another response first.
+    pub fn submit(&mut self, qh: &QueueHandle<Self>) {
+        if self.editing_user {
+            // The username is done, the password comes next
+            self.editing_user = false;
+            match self.unlock_user.as_deref() {
+                Some("") => self.unlock_user = None,
+                Some(user) => log::info!("Unlocking as {}", user),
+                None => {}
+            }
+            self.redraw_all(qh);
+            return;
+        }
+
+        if self.lock_state == LockState::Prompt {
+            self.answer_prompt(qh, true);
+            return;
+        }
+
+        // Still waiting on the previous attempt
+        if !self.set_color(qh, LockState::Wait) {
+            return;
+        }
+        self.redraw_all(qh);
+
+        self.auth_attempt = self.next_attempt_id();
+        self.auth_notice = None;
+        let password = mem::take(&mut self.passwd);
+        self.auth_password_len = password.char_count();
+        let login = self.auth_hdl.login();
+        self.auth_user = self.unlock_user.clone().unwrap_or_else(|| login.to_owned());
+        if self.auth_user != login
+            && !auth::is_allowed(
+                &self.auth_user,
+                &self.options.allowed_unlock_users,
+                &self.options.allowed_unlock_groups,
+            )
+        {
+            log::warn!("{} is not allowed to unlock the session of {}", self.auth_user, login);
+            self.finish_auth(qh, auth::Outcome::Rejected);
+            return;
+        }
+
+        // An attempt that timed out may still be stuck in PAM
+        if self.auth_running() {
+            if self.auth_timeouts < AUTH_MAX_TIMEOUTS {
+                // Don't pile up more threads behind it, this attempt starts once it's done
+                log::warn!("The previous authentication attempt is still running");
+                self.auth_notice =
+                    Some(auth::Notice::Info("Waiting for the previous attempt".to_owned()));
+                self.auth_queued = Some(password);
+                self.redraw_all(qh);
+                self.start_auth_timer(qh);
+                return;
+            }
+            self.renew_auth();
+        }
+        self.spawn_check(qh, password);
+    }
+
+    /// Check `password` for the current attempt on a worker thread, giving up on it after the
+    /// auth timeout.
+    fn spawn_check(&mut self, qh: &QueueHandle<Self>, password: SecretBuffer) {
+        let id = self.auth_attempt;
+        let user = self.auth_user.clone();
+        match self.auth_hdl.spawn_check(id, user, password, self.auth_sender.clone()) {
+            Ok((responder, thread)) => {
+                self.auth_responder = Some(responder);
+                self.auth_thread = Some(thread);
+                self.auth_thread_attempt = id;
+            }
+            Err(err) => {
+                log::error!("Failed to start authentication thread: {}", err);
+                self.finish_auth(qh, auth::Outcome::Unavailable);
+                return;
+            }
+        }
+
+        self.cancel_auth_timer();
+        self.start_auth_timer(qh);
+    }
+
+    /// Start the attempt that was waiting for a stuck one to finish, if it's still waiting.
+    fn start_queued(&mut self, qh: &QueueHandle<Self>) {
+        if let Some(password) = self.auth_queued.take() {
+            if self.lock_state == LockState::Wait {
+                self.auth_notice = None;
+                self.redraw_all(qh);
+                self.spawn_check(qh, password);
+            }
+        }
+    }
+
+    /// Give up on the thread stuck in an earlier attempt and authenticate with a fresh handle from
+    /// now on, in case the stuck one holds on to something the next attempt needs.
+    fn renew_auth(&mut self) {
+        log::error!(
+            "Authentication is stuck after {} timeouts, starting over with a fresh handle",
+            self.auth_timeouts
+        );
+        match auth::LockAuth::new(self.options.auth_backend, self.options.pam_service.as_deref()) {
+            Ok(auth_hdl) => self.auth_hdl = Arc::new(auth_hdl),
+            Err(err) => log::error!("Failed to create an authentication handle: {}", err),
+        }
+        // The stuck thread is left to finish on its own, its result is discarded
+        self.auth_thread = None;
+        self.auth_timeouts = 0;
+    }
+
+    /// Returns true while the thread of the last attempt is running, even if we gave up on it.
+    fn auth_running(&mut self) -> bool {
+        is_running(&mut self.auth_thread)
+    }
+
+    /// Switch keyboard input to the username field, if other users may unlock at all.
+    fn edit_unlock_user(&mut self, qh: &QueueHandle<Self>) {
+        if self.options.allowed_unlock_users.is_empty()
+            && self.options.allowed_unlock_groups.is_empty()
+        {
+            return;
+        }
+        if !matches!(self.lock_state, LockState::Init | LockState::Input) {
+            return;
+        }
+
+        log::info!("Unlocking as another user");
+        self.passwd.clear();
+        self.unlock_user = Some(String::new());
+        self.editing_user = true;
+        self.redraw_all(qh);
+    }
+
+    /// Delete a character or type the one produced by `keysym`, if any. With Ctrl held, U clears
+    /// the field and W or BackSpace delete a word, other keys are ignored.
+    fn edit(&mut self, keysym: Keysym) {
+        if self.modifiers.ctrl {
+            match keysym {
+                Keysym::u | Keysym::U => self.clear_field(),
+                Keysym::w | Keysym::W | Keysym::BackSpace => self.pop_word(),
+                _ => {}
+            }
+            return;
+        }
+
+        match keysym {
+            Keysym::Delete | Keysym::BackSpace => self.pop_char(),
+            _ => {
+                if let Some(ch) = keysym.key_char() {
+                    self.push_char(ch)
                 }
+            }
+        }
+    }
 
-                self.lock_state = state;
-                self.color = self.options.wait_color;
+    /// Called at the compositor's repeat rate while a key is held.
+    fn repeat_key(
+        &mut self,
+        keyboard: &wl_keyboard::WlKeyboard,
+        event: smithay_client_toolkit::seat::keyboard::KeyEvent,
+    ) {
+        let index = match self.keyboard_seat(keyboard) {
+            Some(index) => index,
+            None => return,
+        };
+        match self.seats[index].repeat {
+            Some((raw_code, state)) if raw_code == event.raw_code && state == self.lock_state => {
+                self.modifiers = self.seats[index].modifiers;
+                let keysym = self.seats[index]
+                    .layouts
+                    .keysym(event.raw_code, &self.modifiers)
+                    .unwrap_or(event.keysym);
+                if !self.run_binding(keysym) {
+                    self.edit(keysym);
+                }
             }
-            lock::LockState::Wait => {
-                if state == lock::LockState::Fail {
-                    self.lock_state = state;
-                    self.color = self.options.fail_color;
+            // Submitted, failed or anything else happened since the key was pressed
+            _ => self.seats[index].repeat = None,
+        }
+    }
 
-                    if let Some(command) = &self.options.fail_command {
-                        if let Err(err) = Command::new("sh").arg("-c").arg(command).spawn() {
-                            log::warn!("Error executing fail command \"{}\": {}", command, err);
-                        }
-                    }
+    /// The index of the record of `seat`, adding one if it's new.
+    fn seat_input(&mut self, seat: &wl_seat::WlSeat) -> usize {
+        match self.seats.iter().position(|input| input.seat == *seat) {
+            Some(index) => index,
+            None => {
+                self.seats.push(seat::SeatInput::new(seat.clone()));
+                self.seats.len() - 1
+            }
+        }
+    }
+
+    fn keyboard_seat(&self, keyboard: &wl_keyboard::WlKeyboard) -> Option<usize> {
+        self.seats.iter().position(|input| input.keyboard.as_ref() == Some(keyboard))
+    }
+
+    /// All seats type into the same password. Once another seat starts typing, whatever was typed
+    /// so far is discarded, so that input from different seats never mixes.
+    fn switch_input_seat(&mut self, seat: &wl_seat::WlSeat) {
+        if self.input_seat.as_ref() == Some(seat) {
+            return;
+        }
+        if self.input_seat.is_some() {
+            log::info!("Input from another seat, discarding the input so far");
+            self.clear_field();
+        }
+        self.input_seat = Some(seat.clone());
+    }
+
+    /// Take on the layout of the keyboard of the seat at `index`, logging and redrawing if it
+    /// changed.
+    fn update_layout(&mut self, qh: &QueueHandle<Self>, index: usize) {
+        let layout = self.seats[index].layouts.name().map(str::to_owned);
+        if layout == self.layout {
+            return;
+        }
+        if let Some(name) = &layout {
+            log::info!("Keyboard layout: {}", name);
+        }
+        self.layout = layout;
+        self.redraw_all(qh);
+    }
+
+    /// Cover `output` with a lock surface, unless it already has one or we're done locking.
+    fn create_lock_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let session_lock = match &self.session_lock {
+            Some(session_lock) => session_lock,
+            None => return,
+        };
+        if self.lock_surfaces.iter().any(|lock_surface| lock_surface.output == output) {
+            return;
+        }
+
+        let surface = self.compositor_state.create_surface(qh);
+        let fractional = match (&self.fractional_scale_manager, &self.viewporter) {
+            (Some(manager), Some(viewporter)) => Some((
+                manager.get_fractional_scale(&surface, qh, surface.clone()),
+                viewporter.get_viewport(&surface, qh, ()),
+            )),
+            _ => None,
+        };
+        let lock_surface = session_lock.create_lock_surface(surface, &output, qh);
+        let mut lock_surface = surface::LockSurface::new(output, lock_surface, fractional);
+
+        // Until the compositor tells us the preferred scale, go by the output's
+        if let Some(info) = self.output_state.info(&lock_surface.output) {
+            lock_surface.scale = info.scale_factor.max(1) as u32 * surface::SCALE_DENOMINATOR;
+        }
+        self.lock_surfaces.push(lock_surface);
+    }
+
+    fn lock_surface(&self, surface: &wl_surface::WlSurface) -> Option<usize> {
+        self.lock_surfaces
+            .iter()
+            .position(|lock_surface| lock_surface.surface.wl_surface() == surface)
+    }
 
-                    return true;
+    /// Render the lock surface at `index` at `scale`, in 120ths, from now on.
+    fn set_scale(&mut self, qh: &QueueHandle<Self>, index: usize, scale: u32) {
+        let lock_surface = &mut self.lock_surfaces[index];
+        if lock_surface.scale == scale {
+            return;
+        }
+        lock_surface.scale = scale;
+        log::info!("Rendering at a scale of {}", lock_surface.scale_factor());
+        self.redraw(qh, index);
+    }
+
+    /// Press the PIN pad key at `position` on `surface`, if the pad is shown.
+    fn tap(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        seat: &wl_seat::WlSeat,
+        surface: &wl_surface::WlSurface,
+        position: (f64, f64),
+    ) {
+        if !self.show_pin_pad() {
+            return;
+        }
+        let lock_surface = match self.lock_surface(surface) {
+            Some(index) => &self.lock_surfaces[index],
+            None => return,
+        };
+        let (width, height) = match lock_surface.buffer_size() {
+            Some(size) => size,
+            None => return,
+        };
+        // The pad is laid out in buffer pixels, positions are in logical ones
+        let scale = lock_surface.scale_factor();
+        let pad = pinpad::PinPad::new(width as usize, height as usize);
+        if let Some(button) = pad.button_at(position.0 * scale, position.1 * scale) {
+            self.press_button(qh, seat, button);
+        }
+    }
+
+    /// Handle a PIN pad key like the corresponding key of a keyboard.
+    fn press_button(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        seat: &wl_seat::WlSeat,
+        button: pinpad::Button,
+    ) {
+        if !self.accepts_input(qh) {
+            return;
+        }
+        self.switch_input_seat(seat);
+        if self.set_color(qh, LockState::Input) {
+            self.redraw_all(qh);
+        }
+
+        match button {
+            pinpad::Button::Digit(digit) => self.push_char(digit),
+            pinpad::Button::Backspace => self.pop_char(),
+            pinpad::Button::Enter => self.submit(qh),
+        }
+    }
+
+    /// The PIN pad is shown on touch screens and whenever there's no keyboard to type with.
+    fn show_pin_pad(&self) -> bool {
+        self.seats.iter().any(|input| input.touch.is_some())
+            || self.seats.iter().all(|input| input.keyboard.is_none())
+    }
+
+    /// Type `ch` into the username field while it's being edited, otherwise the password.
+    fn push_char(&mut self, ch: char) {
+        match &mut self.unlock_user {
+            Some(user) if self.editing_user => user.push(ch),
+            _ => {
+                if !self.passwd.push(ch) {
+                    log::warn!("Password too long, ignoring input");
                 }
+            }
+        }
+    }
+
+    /// Delete back to the start of the last word, including any whitespace following it.
+    fn pop_word(&mut self) {
+        while self.last_char().map_or(false, char::is_whitespace) {
+            self.pop_char();
+        }
+        while self.last_char().map_or(false, |ch| !ch.is_whitespace()) {
+            self.pop_char();
+        }
+    }
+
+    fn clear_field(&mut self) {
+        match &mut self.unlock_user {
+            Some(user) if self.editing_user => user.clear(),
+            _ => self.passwd.clear(),
+        }
+    }
+
+    fn last_char(&self) -> Option<char> {
+        match &self.unlock_user {
+            Some(user) if self.editing_user => user.chars().next_back(),
+            _ => self.passwd.as_str().chars().next_back(),
+        }
+    }
+
+    fn pop_char(&mut self) {
+        match &mut self.unlock_user {
+            Some(user) if self.editing_user => {
+                user.pop();
+            }
+            _ => {
+                self.passwd.pop();
+            }
+        }
+    }
+
+    /// Send the typed response to the pending prompt, or abort the attempt if `send` is false.
+    /// Either way the authenticator reports back, so we go back to waiting on it.
+    fn answer_prompt(&mut self, qh: &QueueHandle<Self>, send: bool) {
+        if !self.set_color(qh, LockState::Wait) {
+            return;
+        }
+        self.redraw_all(qh);
+
+        self.auth_prompt = None;
+        let response = mem::take(&mut self.passwd);
+        if send {
+            if let Some(responder) = &self.auth_responder {
+                // The attempt already ended if this fails, its result is on the way
+                let _ = responder.send(response);
+            }
+        } else {
+            self.auth_responder = None;
+        }
+
+        self.start_auth_timer(qh);
+    }
 
-                if state == lock::LockState::Success {
-                    self.lock_state = state;
-
-                    self.loop_handle
-                        .insert_source(Timer::immediate(), |_, _, app_data| {
-                            // Unlock the lock
-                            app_data.session_lock.take().unwrap().unlock();
-                            // Sync connection to make sure compostor receives destroy
-                            app_data.conn.roundtrip().unwrap();
-                            // Then we can exit
-                            app_data.exit = true;
-                            TimeoutAction::Drop
-                        })
-                        .unwrap();
-
-                    return true;
+    /// Give up on the current attempt after the auth timeout. If the timer can't be added the
+    /// attempt runs without a timeout.
+    fn start_auth_timer(&mut self, qh: &QueueHandle<Self>) {
+        let id = self.auth_attempt;
+        let qh = qh.clone();
+        self.auth_timer = self.add_timer(self.options.auth_timeout, move |app_data| {
+            app_data.auth_timer = None;
+            if let Some(outcome) = auth::timeout_outcome(id, app_data.auth_attempt) {
+                log::warn!("Authentication timed out");
+                if app_data.auth_running() {
+                    app_data.auth_timeouts += 1;
                 }
+                app_data.finish_auth(&qh, outcome);
+            }
+        });
+    }
+
+    fn cancel_auth_timer(&mut self) {
+        if let Some(token) = self.auth_timer.take() {
+            self.loop_handle.remove(token);
+        }
+    }
 
-                return false;
+    /// Handle an event of an authentication attempt. Events of attempts that have already timed
+    /// out are discarded.
+    pub fn handle_auth(&mut self, qh: &QueueHandle<Self>, message: auth::AuthMessage) {
+        if message.id == self.fingerprint_attempt {
+            self.handle_fingerprint(qh, message.event);
+            return;
+        }
+        if message.id != self.auth_attempt {
+            // The stuck attempt is done at last, so the one waiting for it can start
+            if message.id == self.auth_thread_attempt {
+                if let auth::AuthEvent::Done(_) = message.event {
+                    self.start_queued(qh);
+                }
             }
-            lock::LockState::Fail => {
-                if state != lock::LockState::Input {
-                    return false;
+            return;
+        }
+
+        match message.event {
+            auth::AuthEvent::Prompt(prompt) => {
+                if !self.set_color(qh, LockState::Prompt) {
+                    return;
                 }
+                log::info!("Authentication prompt: {}", prompt.message);
+                // Anything typed ahead was meant for the next attempt, not as the answer
+                self.passwd.clear();
+                self.auth_prompt = Some(prompt);
+                self.redraw_all(qh);
 
-                self.lock_state = state;
-                self.color = self.options.input_color;
+                // Waiting on the user now, the timeout starts over once the prompt is answered
+                self.cancel_auth_timer();
+            }
+            auth::AuthEvent::Notice(notice) => {
+                match &notice {
+                    auth::Notice::Info(text) => log::info!("Authentication info: {}", text),
+                    auth::Notice::Error(text) => log::warn!("Authentication error: {}", text),
+                }
+                self.auth_notice = Some(notice);
+                self.redraw_all(qh);
+            }
+            auth::AuthEvent::Done(outcome) => {
+                self.auth_timeouts = 0;
+                self.finish_auth(qh, outcome);
             }
-            lock::LockState::Success => {}
         }
+    }
 
-        return true;
+    /// Password and fingerprint attempts share one channel, so they draw their ids from a common
+    /// counter.
+    fn next_attempt_id(&mut self) -> u64 {
+        self.attempt_ids += 1;
+        self.attempt_ids
     }
 
-    pub fn redraw(
-        &self,
-        qh: &QueueHandle<Self>,
-        session_lock_surface: &SessionLockSurface,
-        width: i32,
-        height: i32,
-    ) {
-        let mut pool = RawPool::new(width as usize * height as usize * 4, &self.shm).unwrap();
+    /// Start a fingerprint attempt running alongside password entry, if configured. Attempts
+    /// aren't timed out, as they can't be interrupted and the PAM module gives up on its own.
+    fn start_fingerprint(&mut self, qh: &QueueHandle<Self>) {
+        let fingerprint_hdl = match &self.fingerprint_hdl {
+            Some(fingerprint_hdl) => Arc::clone(fingerprint_hdl),
+            None => return,
+        };
+
+        // Only one attempt at a time can use the reader, and none during a lockout
+        if is_running(&mut self.fingerprint_thread) || self.lockout_state().is_some() {
+            self.restart_fingerprint(qh, FINGERPRINT_BACKOFF.delay(1));
+            return;
+        }
+
+        self.fingerprint_attempt = self.next_attempt_id();
+        self.fingerprint_started = Instant::now();
+        match fingerprint_hdl.spawn_passive(self.fingerprint_attempt, self.auth_sender.clone()) {
+            Ok(thread) => self.fingerprint_thread = Some(thread),
+            Err(err) => {
+                log::error!("Failed to start fingerprint authentication thread: {}", err);
+                self.retry_fingerprint(qh);
+            }
+        }
+    }
+
+    /// Start a new fingerprint attempt after `delay`, or once the lockout is over if that's later.
+    fn restart_fingerprint(&mut self, qh: &QueueHandle<Self>, delay: Duration) {
+        let delay = match self.lockout_state() {
+            Some(lockout) => lockout
+                .lockout_remaining(SystemTime::now())
+                .map_or(delay, |remaining| remaining.max(delay)),
+            None => delay,
+        };
+        if let Some(token) = self.fingerprint_timer.take() {
+            self.loop_handle.remove(token);
+        }
+        // Invalidate the current attempt until the new one is started
+        self.fingerprint_attempt = self.next_attempt_id();
+
+        let qh = qh.clone();
+        self.fingerprint_timer = self.add_timer(delay, move |app_data| {
+            app_data.fingerprint_timer = None;
+            app_data.start_fingerprint(&qh);
+        });
+        if self.fingerprint_timer.is_none() {
+            log::error!("Fingerprint authentication disabled");
+        }
+    }
+
+    /// Start a new fingerprint attempt after an error, backing off further with each consecutive
+    /// one and giving up after `FINGERPRINT_MAX_ERRORS`.
+    fn retry_fingerprint(&mut self, qh: &QueueHandle<Self>) {
+        self.fingerprint_errors += 1;
+        if self.fingerprint_errors >= FINGERPRINT_MAX_ERRORS {
+            log::error!(
+                "Fingerprint authentication disabled after {} consecutive errors",
+                self.fingerprint_errors
+            );
+            self.fingerprint_hdl = None;
+            self.fingerprint_attempt = self.next_attempt_id();
+            return;
+        }
+        self.restart_fingerprint(qh, FINGERPRINT_BACKOFF.delay(self.fingerprint_errors));
+    }
+
+    fn handle_fingerprint(&mut self, qh: &QueueHandle<Self>, event: auth::AuthEvent) {
+        match event {
+            // Never sent for passive attempts
+            auth::AuthEvent::Prompt(_) => {}
+            auth::AuthEvent::Notice(auth::Notice::Info(text)) => {
+                log::info!("Fingerprint info: {}", text)
+            }
+            auth::AuthEvent::Notice(auth::Notice::Error(text)) => {
+                log::warn!("Fingerprint error: {}", text)
+            }
+            auth::AuthEvent::Done(auth::Outcome::Success) => {
+                self.fingerprint_errors = 0;
+                // A lockout may have started while the finger was on the reader
+                if self.lockout_state().is_some() {
+                    log::info!("Fingerprint refused during the lockout");
+                    self.restart_fingerprint(qh, FINGERPRINT_BACKOFF.delay(1));
+                    return;
+                }
+                if !self.lock_state.can_transition(LockState::Success) {
+                    return;
+                }
+
+                // The fingerprint is verified independently of whatever the password path is
+                // doing, so abandon any password attempt and unlock from the current state.
+                self.auth_attempt = self.next_attempt_id();
+                self.cancel_auth_timer();
+                self.auth_responder = None;
+                self.auth_queued = None;
+                self.auth_prompt = None;
+                self.auth_notice = None;
+                self.lockout.reset();
+                self.auth_user = self.auth_hdl.login().to_owned();
+                self.set_color(qh, LockState::Success);
+            }
+            auth::AuthEvent::Done(auth::Outcome::Rejected) => {
+                log::info!("Fingerprint authentication failed");
+                self.fingerprint_errors = 0;
+                let now = SystemTime::now();
+                self.lockout.record_failure(now);
+                self.audit.record_fingerprint_failure(now, self.lockout.failures());
+
+                // A password attempt in progress enters the lockout once it fails itself
+                if let Some(lockout) = self.lockout_state() {
+                    if !matches!(self.lock_state, LockState::Wait | LockState::Prompt)
+                        && self.set_color(qh, lockout)
+                    {
+                        self.run_fail_command();
+                        self.redraw_all(qh);
+                    }
+                }
+                self.restart_fingerprint(qh, FINGERPRINT_BACKOFF.delay(1));
+            }
+            auth::AuthEvent::Done(auth::Outcome::Unavailable) => {
+                if self.fingerprint_started.elapsed() < FINGERPRINT_MIN_ATTEMPT {
+                    log::warn!("Fingerprint authentication is unavailable");
+                    self.retry_fingerprint(qh);
+                } else {
+                    log::info!("Fingerprint authentication ended without a fingerprint");
+                    self.fingerprint_errors = 0;
+                    self.restart_fingerprint(qh, FINGERPRINT_BACKOFF.delay(1));
+                }
+            }
+        }
+    }
 
-        let buffer =
-            pool.create_buffer(0, width, height, width * 4, wl_shm::Format::Argb8888, (), qh);
+    fn finish_auth(&mut self, qh: &QueueHandle<Self>, outcome: auth::Outcome) {
+        if self.lock_state != LockState::Wait {
+            return;
+        }
+
+        self.cancel_auth_timer();
+        self.auth_responder = None;
+        self.auth_queued = None;
+        self.auth_prompt = None;
+        self.auth_notice = None;
+
+        if outcome == auth::Outcome::Success {
+            self.lockout.reset();
+            self.set_color(qh, LockState::Success);
+        } else {
+            // Credentials that couldn't be checked, e.g. after a timeout, aren't a failed attempt
+            let rejected = outcome == auth::Outcome::Rejected;
+            // Most failures are down to Caps Lock, so point it out
+            self.caps_lock_hint = rejected && self.modifiers.caps_lock;
+            if self.caps_lock_hint {
+                log::info!("Authentication failed, Caps Lock is on");
+            } else {
+                log::info!("Authentication failed");
+            }
+            if rejected {
+                let now = SystemTime::now();
+                self.lockout.record_failure(now);
+                self.audit.record_failure(now, self.lockout.failures(), self.auth_password_len);
+            }
+            // The next attempt is for the current user again, unless another username is typed
+            self.unlock_user = None;
+            self.editing_user = false;
+            let state = match self.lockout_state() {
+                Some(lockout) => {
+                    if rejected {
+                        self.run_fail_command();
+                    }
+                    lockout
+                }
+                None => LockState::Fail,
+            };
+            if self.set_color(qh, state) {
+                self.redraw_all(qh);
+            }
+        }
+    }
 
-        // Write the current color to the buffer
-        for (ptr, byte) in pool.mmap().iter_mut().zip(self.color.to_ne_bytes().iter().cycle()) {
-            *ptr = *byte;
+    /// Bring the lock surface at `index` up to date, if anything changed.
+    fn redraw(&mut self, qh: &QueueHandle<Self>, index: usize) {
+        let scene = render::Scene {
+            color: self.color,
+            indicators: self.indicators(),
+            indicator_color: self.options.indicator_color,
+            pin_pad: self.show_pin_pad(),
+            user_field: self.editing_user,
+            caps_lock_hint: self.caps_lock_hint,
+            layout: self.layout.clone(),
+            prompt: self.auth_prompt.clone(),
+            notice: self.auth_notice.clone(),
+            scale: self.lock_surfaces[index].scale_factor(),
+        };
+        self.lock_surfaces[index].draw(&self.shm, qh, &scene);
+    }
+
+    /// The lock modifiers to show, as of the last modifiers event.
+    fn indicators(&self) -> render::Indicators {
+        render::Indicators {
+            caps_lock: self.modifiers.caps_lock,
+            num_lock: self.modifiers.num_lock,
         }
+    }
 
-        session_lock_surface.wl_surface().attach(Some(&buffer), 0, 0);
-        session_lock_surface.wl_surface().damage_buffer(0, 0, width, height);
-        session_lock_surface.wl_surface().commit();
+    pub fn redraw_all(&mut self, qh: &QueueHandle<Self>) {
+        for index in 0..self.lock_surfaces.len() {
+            self.redraw(qh, index);
+        }
+    }
+}
 
-        buffer.destroy();
+impl Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> for AppData {
+    fn event(
+        app_data: &mut Self,
+        _buffer: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        busy: &Arc<AtomicBool>,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            busy.store(false, Ordering::Release);
+            // Catch up on anything that couldn't be drawn while all buffers were in use
+            app_data.redraw_all(qh);
+        }
     }
+}
 
-    pub fn redraw_all(&self) {
-        for surface in self.lock_surfaces_out.iter() {
-            self.redraw(qh, &surface.0, surface.1, surface.2);
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, wl_surface::WlSurface> for AppData {
+    fn event(
+        app_data: &mut Self,
+        _fractional_scale: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            if let Some(index) = app_data.lock_surface(surface) {
+                app_data.set_scale(qh, index, scale.max(1));
+            }
         }
     }
 }
@@ -562,9 +1814,12 @@ impl AppData {
 smithay_client_toolkit::delegate_keyboard!(AppData);
 smithay_client_toolkit::delegate_compositor!(AppData);
 smithay_client_toolkit::delegate_pointer!(AppData);
+smithay_client_toolkit::delegate_touch!(AppData);
 smithay_client_toolkit::delegate_output!(AppData);
 smithay_client_toolkit::delegate_session_lock!(AppData);
 smithay_client_toolkit::delegate_shm!(AppData);
 smithay_client_toolkit::delegate_seat!(AppData);
 smithay_client_toolkit::delegate_registry!(AppData);
-wayland_client::delegate_noop!(AppData: ignore wl_buffer::WlBuffer);
+wayland_client::delegate_noop!(AppData: wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1);
+wayland_client::delegate_noop!(AppData: wp_viewporter::WpViewporter);
+wayland_client::delegate_noop!(AppData: wp_viewport::WpViewport);
@@ -1,16 +1,57 @@
+use crate::auth::Backend;
 use crate::color;
 use crate::config::{Config, ConfigError};
+use crate::keys::KeyCombo;
+use crate::lockout::Policy;
 use crate::logger::Logger;
 
 use clap::{crate_authors, crate_description, crate_name, crate_version, Arg, ArgAction, Command};
 
+use std::process;
+use std::time::Duration;
+
+/// Bindings for hardware keys that keep working while locked, unless overridden in the config
+/// file.
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("XF86AudioRaiseVolume", "pactl set-sink-volume @DEFAULT_SINK@ +5%"),
+    ("XF86AudioLowerVolume", "pactl set-sink-volume @DEFAULT_SINK@ -5%"),
+    ("XF86AudioMute", "pactl set-sink-mute @DEFAULT_SINK@ toggle"),
+    ("XF86AudioMicMute", "pactl set-source-mute @DEFAULT_SOURCE@ toggle"),
+    ("XF86MonBrightnessUp", "brightnessctl set +5%"),
+    ("XF86MonBrightnessDown", "brightnessctl set 5%-"),
+];
+
 pub struct Options {
     pub fail_command: Option<String>,
+    pub harden: bool,
+    pub sandbox: bool,
+    pub unlock_command: Option<String>,
 
     pub init_color: u32,
     pub input_color: u32,
     pub wait_color: u32,
     pub fail_color: u32,
+    /// Color of the Caps Lock and Num Lock indicators.
+    pub indicator_color: u32,
+
+    pub fail_timeout: Duration,
+    pub auth_timeout: Duration,
+    pub lockout: Policy,
+
+    /// Cycles through the keyboard layouts while locked.
+    pub layout_switch_key: KeyCombo,
+    /// Commands run when their key combination is pressed while locked. Only set in the config
+    /// file.
+    pub bindings: Vec<(KeyCombo, String)>,
+
+    pub auth_backend: Backend,
+    pub pam_service: Option<String>,
+    pub fingerprint_service: Option<String>,
+
+    /// Users other than the current one that may unlock, by name or group. Only set in the
+    /// config file.
+    pub allowed_unlock_users: Vec<String>,
+    pub allowed_unlock_groups: Vec<String>,
 }
 
 impl Options {
@@ -20,6 +61,26 @@ impl Options {
             Err(err) => Err(err.to_string()),
         };
 
+        let valid_count = |s: &str| match s.parse::<u32>() {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let valid_backend = |s: &str| match s.parse::<Backend>() {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let valid_key_combo = |s: &str| match s.parse::<KeyCombo>() {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let valid_duration = |s: &str| match humantime::parse_duration(s) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+
         // We manually document the default values so that they can override values specified in the
         // config file.
         let matches = Command::new(crate_name!())
@@ -58,6 +119,84 @@ impl Options {
                     .value_name("COLOR")
                     .value_parser(valid_color),
             )
+            .arg(
+                Arg::new("indicator-color")
+                    .long("indicator-color")
+                    .help("Set the color of the bars shown while Caps Lock (top) or Num Lock (bottom) is on. [default: #ffff00]")
+                    .next_line_help(true)
+                    .value_name("COLOR")
+                    .value_parser(valid_color),
+            )
+            .arg(
+                Arg::new("fail-timeout")
+                    .long("fail-timeout")
+                    .help("Time to show the fail color before accepting input again. [default: 2s]")
+                    .next_line_help(true)
+                    .value_name("DURATION")
+                    .value_parser(valid_duration),
+            )
+            .arg(
+                Arg::new("auth-timeout")
+                    .long("auth-timeout")
+                    .help("Give up on an authentication attempt that takes longer than this. [default: 30s]")
+                    .next_line_help(true)
+                    .value_name("DURATION")
+                    .value_parser(valid_duration),
+            )
+            .arg(
+                Arg::new("lockout-threshold")
+                    .long("lockout-threshold")
+                    .help("Refuse input after this many consecutive failed attempts, 0 to disable. [default: 5]")
+                    .next_line_help(true)
+                    .value_name("COUNT")
+                    .value_parser(valid_count),
+            )
+            .arg(
+                Arg::new("lockout-delay")
+                    .long("lockout-delay")
+                    .help("Time to refuse input for once the lockout threshold is reached, doubled with each further failure. [default: 10s]")
+                    .next_line_help(true)
+                    .value_name("DURATION")
+                    .value_parser(valid_duration),
+            )
+            .arg(
+                Arg::new("lockout-max-delay")
+                    .long("lockout-max-delay")
+                    .help("Never refuse input for longer than this after a failed attempt.")
+                    .next_line_help(true)
+                    .value_name("DURATION")
+                    .value_parser(valid_duration),
+            )
+            .arg(
+                Arg::new("layout-switch-key")
+                    .long("layout-switch-key")
+                    .help("Key combination that switches to the next keyboard layout, e.g. Ctrl+Alt+space. [default: Super+space]")
+                    .next_line_help(true)
+                    .value_name("KEYS")
+                    .value_parser(valid_key_combo),
+            )
+            .arg(
+                Arg::new("auth-backend")
+                    .long("auth-backend")
+                    .help("Backend to authenticate with, one of pam, shadow or mock. The mock backend is for testing only and requires WAYLOCK_INSECURE_MOCK_AUTH=1. [default: pam]")
+                    .next_line_help(true)
+                    .value_name("BACKEND")
+                    .value_parser(valid_backend),
+            )
+            .arg(
+                Arg::new("pam-service")
+                    .long("pam-service")
+                    .help("PAM service to authenticate with. [default: first of waylock, login, system-auth]")
+                    .next_line_help(true)
+                    .value_name("SERVICE")
+            )
+            .arg(
+                Arg::new("fingerprint-service")
+                    .long("fingerprint-service")
+                    .help("PAM service to authenticate with a fingerprint alongside the password, e.g. waylock-fingerprint.")
+                    .next_line_help(true)
+                    .value_name("SERVICE")
+            )
             .arg(
                 Arg::new("config")
                     .long("config")
@@ -72,13 +211,37 @@ impl Options {
                     .next_line_help(true)
                     .value_name("COMMAND")
             )
+            .arg(
+                Arg::new("no-harden")
+                    .long("no-harden")
+                    .action(ArgAction::SetTrue)
+                    .help("Allow core dumps and ptrace, and don't lock memory. Only useful for debugging.")
+            )
+            .arg(
+                Arg::new("sandbox")
+                    .long("sandbox")
+                    .action(ArgAction::SetTrue)
+                    .help("Restrict filesystem access and syscalls once initialized. Can't be used with PAM.")
+            )
+            .arg(
+                Arg::new("unlock-command")
+                    .long("unlock-command")
+                    .help("Command to run on unlock if there were failed attempts since the last unlock. Executed with `sh -c <COMMAND>`, with $WAYLOCK_FAILED_ATTEMPTS and $WAYLOCK_SUMMARY set.")
+                    .next_line_help(true)
+                    .value_name("COMMAND")
+            )
             .arg(
                 Arg::new("verbosity")
                     .short('v')
                     .action(ArgAction::Count)
                     .help("Enable verbose logging, repeat for greater effect (e.g. -vvv).")
             )
-            .get_matches();
+            .try_get_matches()
+            // clap exits with 2 on invalid arguments, which we use for a missing session lock
+            .unwrap_or_else(|err| {
+                let _ = err.print();
+                process::exit(if err.use_stderr() { 1 } else { 0 });
+            });
 
         // This is fine to unwrap, as it only fails when called more than once, and this is the
         // only call site
@@ -95,6 +258,28 @@ impl Options {
             Some(cmd) => Some(cmd.clone()),
             None => None,
         }; //.map(str::to_owned);
+        let mut sandbox = matches.get_flag("sandbox");
+        let mut unlock_command = matches.get_one::<String>("unlock-command").cloned();
+        let mut lockout_threshold =
+            matches.get_one::<String>("lockout-threshold").map(|s| s.parse::<u32>().unwrap());
+        let mut lockout_delay = matches
+            .get_one::<String>("lockout-delay")
+            .map(|s| humantime::parse_duration(s).unwrap());
+        let mut lockout_max_delay = matches
+            .get_one::<String>("lockout-max-delay")
+            .map(|s| humantime::parse_duration(s).unwrap());
+        let mut layout_switch_key =
+            matches.get_one::<String>("layout-switch-key").map(|s| s.parse::<KeyCombo>().unwrap());
+        let mut auth_backend =
+            matches.get_one::<String>("auth-backend").map(|s| s.parse::<Backend>().unwrap());
+        let mut pam_service = matches.get_one::<String>("pam-service").cloned();
+        let mut fingerprint_service = matches.get_one::<String>("fingerprint-service").cloned();
+        let mut bindings: Vec<(KeyCombo, String)> = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(keys, command)| (keys.parse().unwrap(), command.to_owned()))
+            .collect();
+        let mut allowed_unlock_users = None;
+        let mut allowed_unlock_groups = None;
 
         // The vaildator supplied to clap will deny any colors that can't be safetly unwrapped.
         let mut init_color =
@@ -105,17 +290,51 @@ impl Options {
             matches.get_one::<String>("wait-color").map(|s| color::from_str(s).unwrap());
         let mut fail_color =
             matches.get_one::<String>("fail-color").map(|s| color::from_str(s).unwrap());
+        let mut indicator_color =
+            matches.get_one::<String>("indicator-color").map(|s| color::from_str(s).unwrap());
+        let mut fail_timeout = matches
+            .get_one::<String>("fail-timeout")
+            .map(|s| humantime::parse_duration(s).unwrap());
+        let mut auth_timeout = matches
+            .get_one::<String>("auth-timeout")
+            .map(|s| humantime::parse_duration(s).unwrap());
 
         // It's fine if there's no config file, but if we encountered an error report it.
         match Config::new(matches.get_one::<String>("config").map(|s| s.as_str())) {
             Ok(config) => {
                 fail_command = fail_command.or_else(|| config.fail_command.clone());
+                unlock_command = unlock_command.or_else(|| config.unlock_command.clone());
+                sandbox = sandbox || config.sandbox.unwrap_or(false);
+                layout_switch_key = layout_switch_key.or(config.layout_switch_key);
+                auth_backend = auth_backend.or(config.auth_backend);
+                pam_service = pam_service.or_else(|| config.pam_service.clone());
+                fingerprint_service =
+                    fingerprint_service.or_else(|| config.fingerprint_service.clone());
+                auth_timeout = auth_timeout.or(config.auth_timeout);
+                // Configured bindings replace the defaults for the same keys, an empty command
+                // removes the binding
+                for (keys, command) in config.bindings.iter().flatten() {
+                    bindings.retain(|(default, _)| default != keys);
+                    if !command.is_empty() {
+                        bindings.push((*keys, command.clone()));
+                    }
+                }
+                allowed_unlock_users = config.allowed_unlock_users.clone();
+                allowed_unlock_groups = config.allowed_unlock_groups.clone();
+                if let Some(lockout) = &config.lockout {
+                    lockout_threshold = lockout_threshold.or(lockout.threshold);
+                    lockout_delay = lockout_delay.or(lockout.delay);
+                    lockout_max_delay = lockout_max_delay.or(lockout.max_delay);
+                }
                 if let Some(colors) = &config.colors {
                     let make_solid = |c| 0xff00_0000 | c;
                     init_color = init_color.or_else(|| colors.init_color.map(make_solid));
                     input_color = input_color.or_else(|| colors.input_color.map(make_solid));
                     wait_color = wait_color.or_else(|| colors.wait_color.map(make_solid));
                     fail_color = fail_color.or_else(|| colors.fail_color.map(make_solid));
+                    indicator_color =
+                        indicator_color.or_else(|| colors.indicator_color.map(make_solid));
+                    fail_timeout = fail_timeout.or(colors.fail_timeout);
                 }
             }
             Err(ConfigError::NotFound) => {}
@@ -125,10 +344,33 @@ impl Options {
         // These unwrap_or's are the defaults
         Self {
             fail_command,
+            harden: !matches.get_flag("no-harden"),
+            sandbox,
+            unlock_command,
             init_color: init_color.unwrap_or(0xffff_ffff),
             input_color: input_color.unwrap_or(0xff00_00ff),
             wait_color: wait_color.unwrap_or(0xff00_ff00),
             fail_color: fail_color.unwrap_or(0xffff_0000),
+            indicator_color: indicator_color.unwrap_or(0xffff_ff00),
+            fail_timeout: fail_timeout.unwrap_or(Duration::from_secs(2)),
+            auth_timeout: auth_timeout.unwrap_or(Duration::from_secs(30)),
+            lockout: Policy {
+                threshold: lockout_threshold.unwrap_or(5),
+                delay: lockout_delay.unwrap_or(Duration::from_secs(10)),
+                max_delay: lockout_max_delay,
+            },
+            layout_switch_key: layout_switch_key.unwrap_or_else(|| "Super+space".parse().unwrap()),
+            bindings,
+            auth_backend: auth_backend.unwrap_or_default(),
+            pam_service,
+            fingerprint_service,
+            allowed_unlock_users: allowed_unlock_users.unwrap_or_default(),
+            allowed_unlock_groups: allowed_unlock_groups.unwrap_or_default(),
         }
     }
+
+    /// Returns true if any authentication goes through PAM, which may rely on setuid helpers.
+    pub fn uses_pam(&self) -> bool {
+        self.auth_backend == Backend::Pam || self.fingerprint_service.is_some()
+    }
 }
@@ -0,0 +1,182 @@
+/// A key of the on-screen PIN pad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Digit(char),
+    Backspace,
+    Enter,
+}
+
+/// The keys row by row, laid out like a phone's keypad.
+const BUTTONS: [[Button; 3]; 4] = [
+    [Button::Digit('1'), Button::Digit('2'), Button::Digit('3')],
+    [Button::Digit('4'), Button::Digit('5'), Button::Digit('6')],
+    [Button::Digit('7'), Button::Digit('8'), Button::Digit('9')],
+    [Button::Backspace, Button::Digit('0'), Button::Enter],
+];
+
+/// Width of the key labels in glyph pixels, the glyphs are 7 high.
+const GLYPH_WIDTH: usize = 5;
+
+/// 5x7 bitmaps of the key labels, one byte per row with the leftmost pixel in bit 4.
+fn glyph(button: Button) -> [u8; 7] {
+    match button {
+        Button::Digit('0') => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        Button::Digit('1') => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        Button::Digit('2') => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        Button::Digit('3') => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        Button::Digit('4') => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        Button::Digit('5') => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        Button::Digit('6') => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        Button::Digit('7') => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        Button::Digit('8') => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        Button::Digit('9') => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        Button::Digit(_) => [0; 7],
+        // A left arrow
+        Button::Backspace => [0x00, 0x04, 0x08, 0x1f, 0x08, 0x04, 0x00],
+        // A check mark
+        Button::Enter => [0x00, 0x01, 0x01, 0x02, 0x14, 0x08, 0x00],
+    }
+}
+
+/// The placement of the PIN pad, centered on a surface of a given size.
+pub struct PinPad {
+    x: usize,
+    y: usize,
+    /// Size of a key, which are square.
+    cell: usize,
+    gap: usize,
+}
+
+impl PinPad {
+    pub fn new(width: usize, height: usize) -> Self {
+        // Fits 3x4 keys with gaps of a quarter key into half of the shorter side
+        let cell = (width.min(height) / 8).max(1);
+        let gap = cell / 4;
+        let pad_width = 3 * cell + 2 * gap;
+        let pad_height = 4 * cell + 3 * gap;
+        Self {
+            x: width.saturating_sub(pad_width) / 2,
+            y: height.saturating_sub(pad_height) / 2,
+            cell,
+            gap,
+        }
+    }
+
+    /// The key at the surface local position `x`, `y`, if any.
+    pub fn button_at(&self, x: f64, y: f64) -> Option<Button> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let column = self.index(x.checked_sub(self.x)?)?;
+        let row = self.index(y.checked_sub(self.y)?)?;
+        BUTTONS.get(row)?.get(column).copied()
+    }
+
+    /// The index of the key `offset` pixels into the pad along one axis, None if in a gap.
+    fn index(&self, offset: usize) -> Option<usize> {
+        let step = self.cell + self.gap;
+        if offset % step < self.cell {
+            Some(offset / step)
+        } else {
+            None
+        }
+    }
+
+    /// Draw the keys onto `canvas`, an ARGB8888 buffer `width` pixels wide, in colors that stand
+    /// out against the background `color`.
+    pub fn draw(&self, canvas: &mut [u8], width: usize, color: u32) {
+        let (key_color, label_color) = contrast(color);
+        // Labels take up about half of a key
+        let scale = (self.cell / 2 / GLYPH_WIDTH).max(1);
+
+        for (row, buttons) in BUTTONS.iter().enumerate() {
+            for (column, &button) in buttons.iter().enumerate() {
+                let x = self.x + column * (self.cell + self.gap);
+                let y = self.y + row * (self.cell + self.gap);
+                fill_rect(canvas, width, x, y, self.cell, self.cell, key_color);
+
+                let label_x = x + self.cell.saturating_sub(GLYPH_WIDTH * scale) / 2;
+                let label_y = y + self.cell.saturating_sub(7 * scale) / 2;
+                for (i, bits) in glyph(button).iter().enumerate() {
+                    for j in (0..GLYPH_WIDTH).filter(|j| bits & (0x10 >> j) != 0) {
+                        let (px, py) = (label_x + j * scale, label_y + i * scale);
+                        fill_rect(canvas, width, px, py, scale, scale, label_color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Colors for the keys and their labels, lighter on dark backgrounds and darker on light ones.
+fn contrast(color: u32) -> (u32, u32) {
+    let [b, g, r, _] = color.to_le_bytes();
+    let luma = (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000;
+    let shade = |toward: u8| {
+        let mix = |c: u8| ((3 * c as u32 + toward as u32) / 4) as u8;
+        u32::from_le_bytes([mix(b), mix(g), mix(r), 0xff])
+    };
+    if luma > 0x7f {
+        (shade(0x00), 0xff00_0000)
+    } else {
+        (shade(0xff), 0xffff_ffff)
+    }
+}
+
+/// Fill a rectangle of `canvas`, clipped to its bounds.
+fn fill_rect(canvas: &mut [u8], width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
+    let height = canvas.len() / (width * 4);
+    let bytes = color.to_ne_bytes();
+    for row in y..(y + h).min(height) {
+        let line = &mut canvas[row * width * 4..(row + 1) * width * 4];
+        for pixel in line.chunks_exact_mut(4).skip(x).take(w) {
+            pixel.copy_from_slice(&bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Button, PinPad};
+
+    #[test]
+    fn button_at() {
+        // Keys of 100 pixels with gaps of 25, the pad starts at 225, 162
+        let pad = PinPad::new(800, 800);
+        assert_eq!(pad.button_at(275.0, 212.0), Some(Button::Digit('1')));
+        assert_eq!(pad.button_at(525.0, 212.0), Some(Button::Digit('3')));
+        assert_eq!(pad.button_at(275.0, 587.0), Some(Button::Backspace));
+        assert_eq!(pad.button_at(400.0, 587.0), Some(Button::Digit('0')));
+        assert_eq!(pad.button_at(525.0, 587.0), Some(Button::Enter));
+        // In a gap, left of and below the pad
+        assert_eq!(pad.button_at(340.0, 212.0), None);
+        assert_eq!(pad.button_at(100.0, 212.0), None);
+        assert_eq!(pad.button_at(275.0, 700.0), None);
+        assert_eq!(pad.button_at(-1.0, -1.0), None);
+    }
+
+    #[test]
+    fn draw() {
+        let (width, height) = (80, 80);
+        let mut canvas = vec![0; width * height * 4];
+        PinPad::new(width, height).draw(&mut canvas, width, 0xff00_0000);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * width + x) * 4;
+            u32::from_ne_bytes([canvas[i], canvas[i + 1], canvas[i + 2], canvas[i + 3]])
+        };
+        // Outside the pad, and the top left corner of the "1" key lightened against black
+        assert_eq!(pixel(0, 0), 0);
+        assert_eq!(pixel(23, 17), 0xff3f_3f3f);
+        // The top of the label of the "1" key
+        assert_eq!(pixel(27, 18), 0xffff_ffff);
+    }
+
+    #[test]
+    fn tiny_surface() {
+        let mut canvas = vec![0; 4];
+        let pad = PinPad::new(1, 1);
+        pad.draw(&mut canvas, 1, 0xff00_0000);
+        assert_eq!(pad.button_at(0.0, 0.0), Some(Button::Digit('1')));
+    }
+}
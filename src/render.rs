@@ -0,0 +1,174 @@
+use crate::auth::{Notice, Prompt};
+use crate::pinpad::PinPad;
+
+/// Height of the lock modifier indicator bars, and width of the username field bars, in logical
+/// pixels.
+const BAR_HEIGHT: usize = 8;
+/// How many times higher the Caps Lock bar is drawn to point out that it's on after a failure.
+const HINT_SCALE: usize = 3;
+
+/// Lock modifiers shown as bars on top of the lock screen color, Caps Lock along the top edge and
+/// Num Lock along the bottom edge.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Indicators {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// Everything shown on the lock screen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub color: u32,
+    pub indicators: Indicators,
+    pub indicator_color: u32,
+    pub pin_pad: bool,
+    /// Set while the username field has keyboard input, shown as bars along the left and right
+    /// edges.
+    pub user_field: bool,
+    /// Set while showing a failed attempt that was typed with Caps Lock on, the Caps Lock bar is
+    /// drawn `HINT_SCALE` times as high to point it out.
+    pub caps_lock_hint: bool,
+    /// The name of the keyboard layout, e.g. "English (US)". It isn't drawn without a font, but
+    /// a change still brings every surface up to date.
+    pub layout: Option<String>,
+    /// The pending prompt of the authenticator and its last message, not drawn either.
+    pub prompt: Option<Prompt>,
+    pub notice: Option<Notice>,
+    /// Buffer pixels per logical pixel.
+    pub scale: f64,
+}
+
+/// Draw `scene` onto `canvas`, an ARGB8888 buffer `width` pixels wide without row padding.
+pub fn draw(canvas: &mut [u8], width: usize, scene: &Scene) {
+    if width == 0 {
+        return;
+    }
+
+    let height = canvas.len() / (width * 4);
+    let bar_height = (BAR_HEIGHT as f64 * scene.scale).round() as usize;
+    let caps_lock_height = if scene.caps_lock_hint { bar_height * HINT_SCALE } else { bar_height };
+    let indicators = scene.indicators;
+    for (row, line) in canvas.chunks_exact_mut(width * 4).enumerate() {
+        let bar = (indicators.caps_lock && row < caps_lock_height)
+            || (indicators.num_lock && row + bar_height >= height);
+        fill(line, if bar { scene.indicator_color } else { scene.color });
+        if scene.user_field {
+            let bar_width = bar_height.min(width / 2) * 4;
+            let len = line.len();
+            fill(&mut line[..bar_width], scene.indicator_color);
+            fill(&mut line[len - bar_width..], scene.indicator_color);
+        }
+    }
+
+    if scene.pin_pad {
+        PinPad::new(width, height).draw(canvas, width, scene.color);
+    }
+}
+
+fn fill(canvas: &mut [u8], color: u32) {
+    let bytes = color.to_ne_bytes();
+    for pixel in canvas.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{draw, Indicators, Scene, BAR_HEIGHT, HINT_SCALE};
+
+    const WIDTH: usize = 3;
+    // Room for both bars at twice the scale
+    const HEIGHT: usize = 4 * BAR_HEIGHT + 1;
+
+    fn scene(indicators: Indicators, scale: f64) -> Scene {
+        Scene {
+            color: 0xff00_0000,
+            indicators,
+            indicator_color: 0xffff_ff00,
+            pin_pad: false,
+            user_field: false,
+            caps_lock_hint: false,
+            layout: None,
+            prompt: None,
+            notice: None,
+            scale,
+        }
+    }
+
+    /// The pixels of each row of `scene` drawn `width` pixels wide.
+    fn pixels(scene: &Scene, width: usize) -> Vec<Vec<u32>> {
+        let mut canvas = vec![0; width * HEIGHT * 4];
+        draw(&mut canvas, width, scene);
+        canvas
+            .chunks_exact(width * 4)
+            .map(|line| {
+                line.chunks_exact(4)
+                    .map(|pixel| u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The color of every row of a drawn canvas, checking that each row is a single color.
+    fn rows(indicators: Indicators, scale: f64) -> Vec<u32> {
+        pixels(&scene(indicators, scale), WIDTH)
+            .into_iter()
+            .map(|pixels| {
+                assert!(pixels.iter().all(|&pixel| pixel == pixels[0]));
+                pixels[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_indicators() {
+        assert!(rows(Indicators::default(), 1.0).iter().all(|&color| color == 0xff00_0000));
+    }
+
+    #[test]
+    fn caps_lock_on_top() {
+        let rows = rows(Indicators { caps_lock: true, num_lock: false }, 1.0);
+        assert!(rows[..BAR_HEIGHT].iter().all(|&color| color == 0xffff_ff00));
+        assert!(rows[BAR_HEIGHT..].iter().all(|&color| color == 0xff00_0000));
+    }
+
+    #[test]
+    fn num_lock_on_bottom() {
+        let rows = rows(Indicators { caps_lock: false, num_lock: true }, 1.0);
+        assert!(rows[..HEIGHT - BAR_HEIGHT].iter().all(|&color| color == 0xff00_0000));
+        assert!(rows[HEIGHT - BAR_HEIGHT..].iter().all(|&color| color == 0xffff_ff00));
+    }
+
+    #[test]
+    fn scaled_bars() {
+        let rows = rows(Indicators { caps_lock: true, num_lock: false }, 1.5);
+        let bar_height = BAR_HEIGHT * 3 / 2;
+        assert!(rows[..bar_height].iter().all(|&color| color == 0xffff_ff00));
+        assert!(rows[bar_height..].iter().all(|&color| color == 0xff00_0000));
+    }
+
+    #[test]
+    fn caps_lock_hint_raises_bar() {
+        let indicators = Indicators { caps_lock: true, num_lock: false };
+        let hint = Scene { caps_lock_hint: true, ..scene(indicators, 1.0) };
+        let rows: Vec<_> = pixels(&hint, WIDTH).into_iter().map(|row| row[0]).collect();
+        let hint_height = BAR_HEIGHT * HINT_SCALE;
+        assert!(rows[..hint_height].iter().all(|&color| color == 0xffff_ff00));
+        assert!(rows[hint_height..].iter().all(|&color| color == 0xff00_0000));
+
+        // Nothing to point out once Caps Lock is off
+        let off = Scene { caps_lock_hint: true, ..scene(Indicators::default(), 1.0) };
+        assert!(pixels(&off, WIDTH).iter().flatten().all(|&color| color == 0xff00_0000));
+    }
+
+    #[test]
+    fn user_field_on_sides() {
+        let width = 2 * BAR_HEIGHT + 2;
+        let scene = Scene { user_field: true, ..scene(Indicators::default(), 1.0) };
+        for row in pixels(&scene, width) {
+            assert!(row[..BAR_HEIGHT].iter().all(|&color| color == 0xffff_ff00));
+            assert!(row[BAR_HEIGHT..width - BAR_HEIGHT].iter().all(|&color| color == 0xff00_0000));
+            assert!(row[width - BAR_HEIGHT..].iter().all(|&color| color == 0xffff_ff00));
+        }
+    }
+}
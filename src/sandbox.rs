@@ -0,0 +1,544 @@
+use std::ffi::CString;
+use std::fs::{self, DirBuilder};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::{io, mem};
+
+/// Directories PAM, its helpers and the hook commands may read and execute from.
+const READ_PATHS: &[&str] = &[
+    "/bin",
+    "/etc",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/nix/store",
+    "/opt",
+    "/proc",
+    "/run",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var",
+];
+/// Directories with devices such as /dev/null that may be written but not created.
+const DEVICE_PATHS: &[&str] = &["/dev"];
+/// Directories written by PAM modules or as a fallback for shared memory.
+const WRITE_PATHS: &[&str] = &["/dev/shm", "/run/faillock"];
+/// Device classes whose devices the key bindings may control, e.g. with brightnessctl. Their
+/// entries are symlinks into /sys/devices, so each device is allowed where it really is.
+const DEVICE_CLASS_PATHS: &[&str] = &["/sys/class/backlight", "/sys/class/leds"];
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Every access right of the first Landlock ABI, from executing up to creating symlinks.
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+/// Restrict the process to what it still needs once initialized: Landlock limits the filesystem
+/// to reading system directories and writing `writable`, and seccomp to an allowlist of syscalls.
+/// Both are inherited by the authentication threads and hook commands. Only the calling thread
+/// and threads started by it later are restricted, so call this before starting any.
+///
+/// Both require no new privileges, which stops setuid helpers such as PAM's unix_chkpwd from
+/// working, so this mustn't be used with PAM. Failures are logged and leave the respective
+/// restriction out.
+pub fn enter(writable: &[&Path]) {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        log::error!(
+            "Sandbox disabled, failed to set no new privileges: {}",
+            io::Error::last_os_error()
+        );
+        return;
+    }
+
+    for dir in writable {
+        if let Err(err) = DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+            log::error!("Failed to create {}: {}", dir.display(), err);
+        }
+    }
+    let devices = class_devices(DEVICE_CLASS_PATHS.iter().map(Path::new));
+    let writable = writable
+        .iter()
+        .copied()
+        .chain(WRITE_PATHS.iter().map(Path::new))
+        .chain(devices.iter().map(PathBuf::as_path));
+    match restrict_paths(READ_PATHS.iter().map(Path::new), writable) {
+        Ok(true) => log::info!("Filesystem access restricted with Landlock"),
+        Ok(false) => log::warn!("Landlock isn't supported, filesystem access is not restricted"),
+        Err(err) => log::error!("Failed to restrict filesystem access: {}", err),
+    }
+
+    match restrict_syscalls() {
+        Ok(()) => log::info!("Syscalls restricted with seccomp"),
+        Err(err) => log::error!("Failed to restrict syscalls: {}", err),
+    }
+}
+
+/// The devices of the sysfs device classes `classes`, resolved to their directories in
+/// /sys/devices. Classes that don't exist on this system have no devices.
+fn class_devices<'a>(classes: impl Iterator<Item = &'a Path>) -> Vec<PathBuf> {
+    classes
+        .filter_map(|class| fs::read_dir(class).ok())
+        .flatten()
+        .filter_map(|entry| fs::canonicalize(entry.ok()?.path()).ok())
+        .collect()
+}
+
+/// Allow reading `readable` and `DEVICE_PATHS`, and full access beneath `writable`, denying
+/// everything else. Returns false if the kernel doesn't support Landlock.
+fn restrict_paths<'a>(
+    readable: impl Iterator<Item = &'a Path>,
+    writable: impl Iterator<Item = &'a Path>,
+) -> io::Result<bool> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            // Not built into the kernel, or disabled at boot
+            Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Ok(false),
+            _ => Err(err),
+        };
+    }
+
+    // Only handle the rights this kernel knows about, newer ones stay unrestricted
+    let mut handled = ACCESS_FS_V1;
+    if abi >= 2 {
+        handled |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+    let device = ACCESS_READ | ACCESS_FS_WRITE_FILE | (handled & ACCESS_FS_TRUNCATE);
+
+    let attr = RulesetAttr { handled_access_fs: handled };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = ruleset as libc::c_int;
+
+    let rules = readable
+        .map(|path| (path, ACCESS_READ))
+        .chain(DEVICE_PATHS.iter().map(|path| (Path::new(path), device)))
+        .chain(writable.map(|path| (path, handled)));
+    let mut result = Ok(());
+    for (path, access) in rules {
+        if let Err(err) = add_rule(ruleset, path, access) {
+            result = Err(err);
+            break;
+        }
+    }
+
+    result = result.and_then(|()| {
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    });
+    unsafe { libc::close(ruleset) };
+    result.map(|()| true)
+}
+
+/// Allow `access` beneath `path`. Paths that don't exist on this system are skipped.
+fn add_rule(ruleset: libc::c_int, path: &Path, access: u64) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        };
+    }
+
+    let attr = PathBeneathAttr { allowed_access: access, parent_fd: fd };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    let result = if result != 0 { Err(io::Error::last_os_error()) } else { Ok(()) };
+    unsafe { libc::close(fd) };
+    result
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscalls needed by the event loop, the authentication threads, PAM and the hook commands.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // Files
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_close,
+    libc::SYS_openat,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_getdents64,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_readlinkat,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat2,
+    libc::SYS_fchmod,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_ftruncate,
+    libc::SYS_memfd_create,
+    libc::SYS_umask,
+    libc::SYS_getcwd,
+    libc::SYS_chdir,
+    // Memory
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_mlock,
+    libc::SYS_munlock,
+    // Threads, processes and signals
+    libc::SYS_futex,
+    libc::SYS_restart_syscall,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_execve,
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_set_tid_address,
+    libc::SYS_gettid,
+    libc::SYS_getpid,
+    libc::SYS_getppid,
+    libc::SYS_getpgid,
+    libc::SYS_setpgid,
+    libc::SYS_kill,
+    libc::SYS_tgkill,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_prlimit64,
+    libc::SYS_getrlimit,
+    // Event loop
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_timerfd_create,
+    libc::SYS_timerfd_settime,
+    libc::SYS_timerfd_gettime,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    // Sockets, for the compositor and services such as sssd or fprintd
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_shutdown,
+    // Credentials
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getgroups,
+    libc::SYS_getresuid,
+    libc::SYS_getresgid,
+    // Time and miscellaneous
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    // Legacy variants of the above, still used by some libcs and shells
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mkdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_getdents,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_pipe,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_select,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_fork,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_vfork,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_getpgrp,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_time,
+];
+
+/// The prctl options allowed in the sandbox, only ones that read state or make it stricter. Others
+/// such as PR_SET_DUMPABLE could undo the hardening.
+const ALLOWED_PRCTL_OPTIONS: &[libc::c_int] = &[
+    // Thread names
+    libc::PR_SET_NAME,
+    libc::PR_GET_NAME,
+    libc::PR_GET_DUMPABLE,
+    libc::PR_SET_NO_NEW_PRIVS,
+    libc::PR_GET_NO_NEW_PRIVS,
+];
+
+// Classic BPF opcodes, see linux/filter.h
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+
+/// Offsets into struct seccomp_data.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+/// The lower half of the first argument, on little-endian architectures.
+const SECCOMP_DATA_ARG0: u32 = 16;
+
+fn bpf(code: u16, jt: u8, jf: u8, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// Build a filter allowing `syscalls` and failing everything else with ENOSYS, so that libc falls
+/// back to older syscalls where it can. prctl is allowed with `prctl_options` only, and fails with
+/// EPERM otherwise. Other architectures, such as x32, are killed outright.
+fn build_filter(
+    arch: u32,
+    syscalls: &[libc::c_long],
+    prctl_options: &[libc::c_int],
+) -> Vec<libc::sock_filter> {
+    let mut filter = vec![
+        bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARCH),
+        bpf(BPF_JEQ_K, 1, 0, arch),
+        bpf(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS),
+        bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_NR),
+    ];
+    for &nr in syscalls {
+        filter.push(bpf(BPF_JEQ_K, 0, 1, nr as u32));
+        filter.push(bpf(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+    }
+
+    // Skip the option checks for anything but prctl. The kernel takes the option as an int, so
+    // the upper half of the argument doesn't matter.
+    let skip = 2 * prctl_options.len() + 2;
+    filter.push(bpf(BPF_JEQ_K, 0, skip as u8, libc::SYS_prctl as u32));
+    filter.push(bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARG0));
+    for &option in prctl_options {
+        filter.push(bpf(BPF_JEQ_K, 0, 1, option as u32));
+        filter.push(bpf(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+    }
+    filter.push(bpf(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+
+    filter.push(bpf(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+    filter
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn restrict_syscalls() -> io::Result<()> {
+    let mut filter = build_filter(AUDIT_ARCH, ALLOWED_SYSCALLS, ALLOWED_PRCTL_OPTIONS);
+    let prog = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_mut_ptr() };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn restrict_syscalls() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no syscall allowlist for this architecture"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{env, fs, io, process, thread};
+
+    /// Run `f` on a fresh thread so that the sandbox doesn't leak into other tests.
+    fn sandboxed<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+        thread::spawn(move || {
+            assert_eq!(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }, 0);
+            f()
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn forbidden_paths_cant_be_opened() {
+        let dir = env::temp_dir().join(format!("waylock-sandbox-{}", process::id()));
+        let allowed = dir.join("allowed");
+        let forbidden = dir.join("forbidden");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(&forbidden).unwrap();
+        fs::write(forbidden.join("secret"), "hunter2").unwrap();
+
+        let (allowed_dir, forbidden_dir) = (allowed.clone(), forbidden.clone());
+        let result = sandboxed(move || {
+            let readable = ["/etc"];
+            let writable = [allowed_dir.as_path()];
+            match super::restrict_paths(readable.iter().map(Path::new), writable.iter().copied()) {
+                Ok(true) => {}
+                // Nothing to check without Landlock
+                Ok(false) => return None,
+                Err(err) => panic!("{}", err),
+            }
+            Some((
+                fs::write(allowed_dir.join("file"), "ok"),
+                fs::read_dir("/etc").map(|_| ()),
+                fs::read(forbidden_dir.join("secret")).map(|_| ()),
+                fs::write(forbidden_dir.join("new"), "no"),
+                fs::read_dir("/root").map(|_| ()),
+            ))
+        });
+
+        if let Some((write_allowed, read_etc, read_forbidden, write_forbidden, read_root)) = result
+        {
+            write_allowed.unwrap();
+            read_etc.unwrap();
+            for denied in [read_forbidden, write_forbidden, read_root] {
+                assert_eq!(denied.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            }
+        }
+        // Outside the sandbox everything is still accessible
+        fs::read(forbidden.join("secret")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn class_devices_are_resolved() {
+        let dir = env::temp_dir().join(format!("waylock-class-{}", process::id()));
+        let device = dir.join("devices").join("intel_backlight");
+        let class = dir.join("class");
+        fs::create_dir_all(&device).unwrap();
+        fs::create_dir_all(&class).unwrap();
+        std::os::unix::fs::symlink(&device, class.join("intel_backlight")).unwrap();
+
+        let missing = dir.join("missing");
+        let devices = super::class_devices([class.as_path(), missing.as_path()].iter().copied());
+        assert_eq!(devices, [fs::canonicalize(&device).unwrap()]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn syscalls_outside_allowlist_fail() {
+        let (ptrace, pid) = sandboxed(|| {
+            super::restrict_syscalls().unwrap();
+            let ptrace = unsafe { libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) };
+            (io::Error::last_os_error().raw_os_error().filter(|_| ptrace != 0), unsafe {
+                libc::getpid()
+            })
+        });
+        assert_eq!(ptrace, Some(libc::ENOSYS));
+        assert_eq!(pid, process::id() as libc::pid_t);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn prctl_outside_allowlist_fails() {
+        let (dumpable, name) = sandboxed(|| {
+            super::restrict_syscalls().unwrap();
+            let dumpable = unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0) };
+            let dumpable = io::Error::last_os_error().raw_os_error().filter(|_| dumpable != 0);
+            let mut name = [0_u8; 16];
+            let name = unsafe { libc::prctl(libc::PR_GET_NAME, name.as_mut_ptr(), 0, 0, 0) };
+            (dumpable, name)
+        });
+        assert_eq!(dumpable, Some(libc::EPERM));
+        assert_eq!(name, 0);
+    }
+}
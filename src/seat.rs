@@ -0,0 +1,65 @@
+use crate::compose::Compose;
+use crate::layout::Layouts;
+use crate::lock::LockState;
+
+use smithay_client_toolkit::seat::keyboard::Modifiers;
+use wayland_client::protocol::{wl_keyboard, wl_pointer, wl_seat, wl_touch};
+
+/// The input devices of a seat, along with the keyboard state that must not leak between seats.
+pub struct SeatInput {
+    pub seat: wl_seat::WlSeat,
+    pub keyboard: Option<wl_keyboard::WlKeyboard>,
+    pub pointer: Option<wl_pointer::WlPointer>,
+    pub touch: Option<wl_touch::WlTouch>,
+    pub modifiers: Modifiers,
+    pub layouts: Layouts,
+    pub compose: Compose,
+    /// The held key that repeats, and the state it was pressed in. Repeats stop once the state
+    /// changes.
+    pub repeat: Option<(u32, LockState)>,
+}
+
+impl SeatInput {
+    pub fn new(seat: wl_seat::WlSeat) -> Self {
+        Self {
+            seat,
+            keyboard: None,
+            pointer: None,
+            touch: None,
+            modifiers: Modifiers::default(),
+            layouts: Layouts::new(),
+            compose: Compose::new(),
+            repeat: None,
+        }
+    }
+
+    /// Release all input devices, once the seat is gone.
+    pub fn release(&mut self) {
+        self.release_keyboard();
+        self.release_pointer();
+        self.release_touch();
+    }
+
+    pub fn release_keyboard(&mut self) {
+        if let Some(keyboard) = self.keyboard.take() {
+            keyboard.release();
+        }
+        // Nothing is held on a keyboard that's gone
+        self.modifiers = Modifiers::default();
+        self.layouts.set_held(&[]);
+        self.compose.reset();
+        self.repeat = None;
+    }
+
+    pub fn release_pointer(&mut self) {
+        if let Some(pointer) = self.pointer.take() {
+            pointer.release();
+        }
+    }
+
+    pub fn release_touch(&mut self) {
+        if let Some(touch) = self.touch.take() {
+            touch.release();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::ffi::CStr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use std::{ptr, str};
+
+/// Longest password accepted in bytes, further input is dropped.
+pub const CAPACITY: usize = 1024;
+
+/// A fixed-capacity buffer for passwords and other secrets. It never reallocates, is locked in
+/// RAM so that it can't be swapped out, and is wiped whenever its contents shrink and on drop.
+/// The contents are always followed by a nul byte so that they can be handed to C as is.
+pub struct SecretBuffer {
+    buf: Box<[u8; CAPACITY + 1]>,
+    len: usize,
+}
+
+impl SecretBuffer {
+    pub fn new() -> Self {
+        let buf = Box::new([0; CAPACITY + 1]);
+        // Locking may fail due to RLIMIT_MEMLOCK, which only makes swapping the secret possible
+        if unsafe { libc::mlock(buf.as_ptr() as *const libc::c_void, buf.len()) } != 0 {
+            log::warn!("Failed to lock the password buffer in memory");
+        }
+        Self { buf, len: 0 }
+    }
+
+    /// Append `ch`, returning false if it doesn't fit. Nul can't be part of a C string and is
+    /// refused as well.
+    pub fn push(&mut self, ch: char) -> bool {
+        let len = ch.len_utf8();
+        if ch == '\0' || self.len + len > CAPACITY {
+            return false;
+        }
+        ch.encode_utf8(&mut self.buf[self.len..self.len + len]);
+        self.len += len;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.as_str().chars().next_back()?;
+        let len = self.len - ch.len_utf8();
+        self.wipe(len);
+        Some(ch)
+    }
+
+    pub fn clear(&mut self) {
+        self.wipe(0);
+    }
+
+    /// The number of characters in the buffer.
+    pub fn char_count(&self) -> usize {
+        self.as_str().chars().count()
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from chars and truncated at char boundaries
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    pub fn as_c_str(&self) -> &CStr {
+        // push refuses nul and everything past the contents is zeroed
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.buf[..=self.len]) }
+    }
+
+    /// Truncate to `len` bytes, wiping the rest.
+    fn wipe(&mut self, len: usize) {
+        wipe_bytes(&mut self.buf[len..self.len]);
+        self.len = len;
+    }
+}
+
+/// Overwrite `bytes` with zeros in a way the compiler can't optimize out, for secrets that pass
+/// through other buffers on their way into a `SecretBuffer`.
+pub fn wipe_bytes(bytes: &mut [u8]) {
+    for byte in bytes {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl Default for SecretBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        // Not unlocked, as the pages may be shared with other allocations that are still locked,
+        // e.g. by mlockall. The pages stay locked until they're returned to the system.
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SecretBuffer, CAPACITY};
+
+    fn secret(s: &str) -> SecretBuffer {
+        let mut secret = SecretBuffer::new();
+        for ch in s.chars() {
+            assert!(secret.push(ch));
+        }
+        secret
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut secret = secret("hünter2");
+        assert_eq!(secret.pop(), Some('2'));
+        assert_eq!(secret.as_str(), "hünter");
+        assert_eq!(secret.as_c_str().to_bytes(), "hünter".as_bytes());
+        assert_eq!(secret.char_count(), 6);
+    }
+
+    #[test]
+    fn pop_wipes() {
+        let mut secret = secret("ab");
+        secret.pop();
+        secret.pop();
+        assert_eq!(secret.pop(), None);
+        assert!(secret.as_str().is_empty());
+        assert!(secret.buf.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn clear_wipes() {
+        let mut secret = secret("hunter2");
+        secret.clear();
+        assert!(secret.as_str().is_empty());
+        assert!(secret.buf.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn wipe_bytes() {
+        let mut bytes = *b"hunter2";
+        super::wipe_bytes(&mut bytes);
+        assert_eq!(bytes, [0; 7]);
+    }
+
+    #[test]
+    fn refuses_overflow_and_nul() {
+        let mut secret = secret(&"a".repeat(CAPACITY - 1));
+        assert!(!secret.push('\0'));
+        assert!(!secret.push('ü'));
+        assert!(secret.push('a'));
+        assert!(!secret.push('a'));
+        assert_eq!(secret.as_c_str().to_bytes().len(), CAPACITY);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::render::{self, Scene};
+
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_v1, viewporter::client::wp_viewport,
+};
+use smithay_client_toolkit::session_lock::SessionLockSurface;
+use smithay_client_toolkit::shm::{raw::RawPool, CreatePoolError, Shm};
+use wayland_client::protocol::{wl_buffer, wl_output, wl_shm};
+use wayland_client::{Dispatch, QueueHandle};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Scales are kept in 120ths, the unit of wp_fractional_scale_v1.
+pub const SCALE_DENOMINATOR: u32 = 120;
+
+/// Buffers per surface, so that one can be drawn while the compositor still reads another.
+const BUFFER_COUNT: usize = 2;
+
+/// Buffers of one size sharing a pool, reused until the size changes. Each buffer comes with a
+/// flag that is set while the compositor reads from it, and cleared on wl_buffer.release.
+struct Buffers {
+    pool: RawPool,
+    width: i32,
+    height: i32,
+    buffers: Vec<(wl_buffer::WlBuffer, Arc<AtomicBool>)>,
+}
+
+impl Buffers {
+    fn new<D>(
+        shm: &Shm,
+        qh: &QueueHandle<D>,
+        width: i32,
+        height: i32,
+    ) -> Result<Self, CreatePoolError>
+    where
+        D: Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> + 'static,
+    {
+        let len = width * height * 4;
+        let mut pool = RawPool::new(len as usize * BUFFER_COUNT, shm)?;
+        let buffers = (0..BUFFER_COUNT as i32)
+            .map(|i| {
+                let busy = Arc::new(AtomicBool::new(false));
+                let format = wl_shm::Format::Argb8888;
+                let buffer =
+                    pool.create_buffer(i * len, width, height, width * 4, format, busy.clone(), qh);
+                (buffer, busy)
+            })
+            .collect();
+        Ok(Self { pool, width, height, buffers })
+    }
+}
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        for (buffer, _) in &self.buffers {
+            buffer.destroy();
+        }
+    }
+}
+
+/// The lock surface covering an output, created and destroyed along with the output.
+pub struct LockSurface {
+    pub output: wl_output::WlOutput,
+    /// Dropping this destroys the surface.
+    pub surface: SessionLockSurface,
+    /// The logical size from the last configure, None until the first one.
+    size: Option<(u32, u32)>,
+    /// The scale of the output in 120ths.
+    pub scale: u32,
+    /// Set if the compositor supports fractional scaling, the buffer is then scaled down to the
+    /// logical size by the viewport rather than by an integer buffer scale.
+    pub fractional: Option<(wp_fractional_scale_v1::WpFractionalScaleV1, wp_viewport::WpViewport)>,
+    buffers: Option<Buffers>,
+    /// What the surface currently shows, None if it has to be drawn.
+    drawn: Option<Scene>,
+}
+
+impl LockSurface {
+    pub fn new(
+        output: wl_output::WlOutput,
+        surface: SessionLockSurface,
+        fractional: Option<(wp_fractional_scale_v1::WpFractionalScaleV1, wp_viewport::WpViewport)>,
+    ) -> Self {
+        Self {
+            output,
+            surface,
+            size: None,
+            scale: SCALE_DENOMINATOR,
+            fractional,
+            buffers: None,
+            drawn: None,
+        }
+    }
+
+    /// Take on the logical size of a configure, which has to be answered with a new buffer.
+    pub fn configure(&mut self, width: u32, height: u32) {
+        self.size = Some((width, height));
+        self.drawn = None;
+    }
+
+    /// Draw `scene` into a free buffer and commit it, unless the surface already shows it or
+    /// isn't configured yet. If the compositor still holds all buffers nothing is drawn, and the
+    /// scene has to be drawn again once one is released.
+    pub fn draw<D>(&mut self, shm: &Shm, qh: &QueueHandle<D>, scene: &Scene)
+    where
+        D: Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> + 'static,
+    {
+        let (logical_width, logical_height) = match self.size {
+            Some(size) => size,
+            None => return,
+        };
+        let (width, height) = match self.buffer_size() {
+            Some((width, height)) => (width as i32, height as i32),
+            None => return,
+        };
+
+        // Scaling or a new mode change the size, the old buffers are of no use then
+        if self
+            .buffers
+            .as_ref()
+            .map_or(true, |buffers| (buffers.width, buffers.height) != (width, height))
+        {
+            self.buffers = None;
+            self.drawn = None;
+            match Buffers::new(shm, qh, width, height) {
+                Ok(buffers) => self.buffers = Some(buffers),
+                Err(err) => {
+                    log::error!("Failed to create buffers: {}", err);
+                    return;
+                }
+            }
+        }
+        if self.drawn.as_ref() == Some(scene) {
+            return;
+        }
+        let buffers = match &mut self.buffers {
+            Some(buffers) => buffers,
+            None => return,
+        };
+
+        let index = match buffers.buffers.iter().position(|(_, busy)| !busy.load(Ordering::Acquire))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let (buffer, busy) = &buffers.buffers[index];
+        let len = width as usize * height as usize * 4;
+        render::draw(
+            &mut buffers.pool.mmap()[index * len..(index + 1) * len],
+            width as usize,
+            scene,
+        );
+        busy.store(true, Ordering::Release);
+
+        let wl_surface = self.surface.wl_surface();
+        match &self.fractional {
+            // The viewport scales the buffer down to the logical size
+            Some((_, viewport)) => {
+                viewport.set_destination(logical_width as i32, logical_height as i32)
+            }
+            None => wl_surface.set_buffer_scale((self.scale / SCALE_DENOMINATOR) as i32),
+        }
+        wl_surface.attach(Some(buffer), 0, 0);
+        wl_surface.damage_buffer(0, 0, width, height);
+        wl_surface.commit();
+
+        self.drawn = Some(scene.clone());
+    }
+
+    /// The size of the buffer to render at, None until configured.
+    pub fn buffer_size(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.size?;
+        Some((scale(width, self.scale), scale(height, self.scale)))
+    }
+
+    /// The scale as a factor, e.g. 1.5.
+    pub fn scale_factor(&self) -> f64 {
+        f64::from(self.scale) / f64::from(SCALE_DENOMINATOR)
+    }
+}
+
+impl Drop for LockSurface {
+    fn drop(&mut self) {
+        if let Some((fractional_scale, viewport)) = self.fractional.take() {
+            fractional_scale.destroy();
+            viewport.destroy();
+        }
+    }
+}
+
+/// Scale a logical length to buffer pixels, rounding halfway cases away from zero as
+/// wp_fractional_scale_v1 recommends.
+fn scale(length: u32, scale: u32) -> u32 {
+    ((u64::from(length) * u64::from(scale) + u64::from(SCALE_DENOMINATOR / 2))
+        / u64::from(SCALE_DENOMINATOR)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scale;
+
+    #[test]
+    fn scale_lengths() {
+        assert_eq!(scale(1920, 120), 1920);
+        assert_eq!(scale(1920, 240), 3840);
+        assert_eq!(scale(1280, 180), 1920);
+        // 1.25 * 3 = 3.75 and 1.25 * 1 = 1.25
+        assert_eq!(scale(3, 150), 4);
+        assert_eq!(scale(1, 150), 1);
+        // 1.5 * 1 = 1.5 rounds up
+        assert_eq!(scale(1, 180), 2);
+    }
+}